@@ -21,6 +21,7 @@
 #![warn(clippy::rc_buffer)]
 
 use tor_chanmgr::ChanMgr;
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::{fallback::FallbackDir, NetDir};
 use tor_netdoc::types::policy::PortPolicy;
 use tor_proto::circuit::{CircParameters, ClientCirc, UniqId};
@@ -69,6 +70,9 @@ pub struct CircMgr<R: Runtime> {
     /// by this CircMgr.
     circuits: Mutex<CircSet>,
 
+    /// Relays that we must never use in any circuit we build.
+    excluded_relays: Vec<RsaIdentity>,
+
     /// Asynchronous runtime for this circuit manager.
     runtime: R,
 }
@@ -257,14 +261,19 @@ impl TargetPort {
         TargetPort { ipv6: true, port }
     }
 
-    /// Return true if this port is supported by the provided Relay.
-    pub fn is_supported_by(&self, r: &tor_netdir::Relay<'_>) -> bool {
+    /// Return the address family that this port is for.
+    pub fn family(&self) -> tor_netdir::IpAddrFamily {
         if self.ipv6 {
-            r.supports_exit_port_ipv6(self.port)
+            tor_netdir::IpAddrFamily::V6
         } else {
-            r.supports_exit_port_ipv4(self.port)
+            tor_netdir::IpAddrFamily::V4
         }
     }
+
+    /// Return true if this port is supported by the provided Relay.
+    pub fn is_supported_by(&self, r: &tor_netdir::Relay<'_>) -> bool {
+        r.supports_exit_port_on(self.port, self.family())
+    }
 }
 
 impl ExitPolicy {
@@ -302,18 +311,25 @@ enum CircUsage {
 impl TargetCircUsage {
     /// Construct path for a given circuit purpose; return it and the
     /// usage that it _actually_ supports.
+    ///
+    /// The path never uses any relay listed in `excluded`.
     fn build_path<'a, R: Rng>(
         &self,
         rng: &mut R,
         netdir: DirInfo<'a>,
+        excluded: &[RsaIdentity],
     ) -> Result<(TorPath<'a>, CircUsage)> {
         match self {
             TargetCircUsage::Dir => {
-                let path = DirPathBuilder::new().pick_path(rng, netdir)?;
+                let mut builder = DirPathBuilder::new();
+                builder.set_excluded_relays(excluded);
+                let path = builder.pick_path(rng, netdir)?;
                 Ok((path, CircUsage::Dir))
             }
             TargetCircUsage::Exit(p) => {
-                let path = ExitPathBuilder::new(p.clone()).pick_path(rng, netdir)?;
+                let mut builder = ExitPathBuilder::new(p.clone());
+                builder.set_excluded_relays(excluded);
+                let path = builder.pick_path(rng, netdir)?;
                 let policy = path
                     .exit_policy()
                     .expect("ExitPathBuilder gave us a one-hop circuit?");
@@ -359,10 +375,20 @@ impl<R: Runtime> CircMgr<R> {
         CircMgr {
             chanmgr,
             circuits,
+            excluded_relays: Vec::new(),
             runtime,
         }
     }
 
+    /// Never use any relay whose RSA identity is in `excluded` when
+    /// building new circuits.
+    ///
+    /// This also applies to fallback directories.  Circuits that were
+    /// built before this call are not affected.
+    pub fn set_excluded_relays(&mut self, excluded: &[RsaIdentity]) {
+        self.excluded_relays = excluded.to_vec();
+    }
+
     /// Return a circuit suitable for sending one-hop BEGINDIR streams,
     /// launching it if necessary.
     pub async fn get_or_launch_dir(&self, netdir: DirInfo<'_>) -> Result<Arc<ClientCirc>> {
@@ -559,7 +585,7 @@ impl<R: Runtime> CircMgr<R> {
         target_usage: &TargetCircUsage,
     ) -> Result<(Arc<ClientCirc>, CircUsage)> {
         let params = netdir.circ_params();
-        let (path, usage) = target_usage.build_path(rng, netdir)?;
+        let (path, usage) = target_usage.build_path(rng, netdir, &self.excluded_relays)?;
         let circ = path
             .build_circuit(rng, &self.runtime, &self.chanmgr, &params)
             .await?;
@@ -1,13 +1,18 @@
 //! Code to construct paths to a directory for non-anonymous downloads
 use super::*;
 use crate::{DirInfo, Error};
+use tor_linkspec::ChanTarget;
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::{Relay, WeightRole};
 
 use rand::seq::SliceRandom;
 
 /// A PathBuilder that can connect to a directory.
 #[non_exhaustive]
-pub struct DirPathBuilder {}
+pub struct DirPathBuilder {
+    /// Relays (and fallbacks) that we must never use in the path.
+    excluded: Vec<RsaIdentity>,
+}
 
 impl Default for DirPathBuilder {
     fn default() -> Self {
@@ -18,7 +23,15 @@ impl Default for DirPathBuilder {
 impl DirPathBuilder {
     /// Create a new DirPathBuilder.
     pub fn new() -> Self {
-        DirPathBuilder {}
+        DirPathBuilder {
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Never use any relay or fallback whose RSA identity is in
+    /// `excluded`.
+    pub fn set_excluded_relays(&mut self, excluded: &[RsaIdentity]) {
+        self.excluded = excluded.to_vec();
     }
 
     /// Try to create and return a path corresponding to the requirements of
@@ -27,13 +40,22 @@ impl DirPathBuilder {
         // TODO: this will need to learn about directory guards.
         match netdir {
             DirInfo::Fallbacks(f) => {
-                let relay = f.choose(rng);
+                let usable: Vec<_> = f
+                    .iter()
+                    .filter(|fb| !self.excluded.contains(fb.rsa_identity()))
+                    .collect();
+                let relay = usable.choose(rng);
                 if let Some(r) = relay {
                     return Ok(TorPath::FallbackOneHop(r));
                 }
             }
             DirInfo::Directory(netdir) => {
-                let relay = netdir.pick_relay(rng, WeightRole::BeginDir, Relay::is_dir_cache);
+                let relay = netdir.pick_relay_excluding(
+                    rng,
+                    WeightRole::BeginDir,
+                    &self.excluded,
+                    Relay::is_dir_cache,
+                );
                 if let Some(r) = relay {
                     return Ok(TorPath::OneHop(r));
                 }
@@ -42,3 +64,54 @@ impl DirPathBuilder {
         Err(Error::NoRelays("No relays found for use as directory cache".into()).into())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tor_netdir::{fallback::FallbackDir, testnet};
+
+    #[test]
+    fn avoids_excluded_relays() {
+        let netdir = testnet::netdir(&testnet::default_relays());
+        let excluded: Vec<_> = (0..12).filter(|&i| i != 5).map(testnet::rsa_id).collect();
+        let mut builder = DirPathBuilder::new();
+        builder.set_excluded_relays(&excluded);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            match builder.pick_path(&mut rng, (&netdir).into()).unwrap() {
+                TorPath::OneHop(r) => assert_eq!(r.rsa_id(), &testnet::rsa_id(5)),
+                _ => panic!("directory path wasn't a one-hop path"),
+            }
+        }
+
+        builder.set_excluded_relays(&[testnet::rsa_id(5)]);
+        assert!(builder.pick_path(&mut rng, (&netdir).into()).is_ok());
+    }
+
+    #[test]
+    fn avoids_excluded_fallbacks() {
+        let fallbacks: Vec<_> = (0..3)
+            .map(|i| {
+                FallbackDir::new(
+                    testnet::rsa_id(i),
+                    testnet::ed_id(i),
+                    vec!["192.0.2.1:9001".parse().unwrap()],
+                )
+            })
+            .collect();
+        let mut builder = DirPathBuilder::new();
+        builder.set_excluded_relays(&[testnet::rsa_id(0), testnet::rsa_id(2)]);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            match builder.pick_path(&mut rng, fallbacks[..].into()).unwrap() {
+                TorPath::FallbackOneHop(f) => assert!(f.is_same_relay(&fallbacks[1])),
+                _ => panic!("fallback path wasn't a one-hop path"),
+            }
+        }
+
+        builder.set_excluded_relays(&[testnet::rsa_id(0), testnet::rsa_id(1), testnet::rsa_id(2)]);
+        assert!(builder.pick_path(&mut rng, fallbacks[..].into()).is_err());
+    }
+}
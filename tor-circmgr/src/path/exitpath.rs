@@ -2,6 +2,7 @@
 
 use super::*;
 use crate::{DirInfo, Error, TargetPort};
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::WeightRole;
 
 /// A PathBuilder that builds a path to an exit node supporting a given
@@ -9,13 +10,23 @@ use tor_netdir::WeightRole;
 pub struct ExitPathBuilder {
     /// List of ports that the exit needs to support
     wantports: Vec<TargetPort>,
+    /// Relays that we must never use in the path.
+    excluded: Vec<RsaIdentity>,
 }
 
 impl ExitPathBuilder {
     /// Create a new builder that will try to get an exit node
     /// containing all the ports in `ports`.
     pub(crate) fn new(wantports: Vec<TargetPort>) -> Self {
-        ExitPathBuilder { wantports }
+        ExitPathBuilder {
+            wantports,
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Never use any relay whose RSA identity is in `excluded`.
+    pub fn set_excluded_relays(&mut self, excluded: &[RsaIdentity]) {
+        self.excluded = excluded.to_vec();
     }
 
     /// Return true if `r` supports every port in `self.wantports`
@@ -32,15 +43,19 @@ impl ExitPathBuilder {
             DirInfo::Directory(d) => d,
         };
         let exit = netdir
-            .pick_relay(rng, WeightRole::Exit, |r| self.ports_supported_by(r))
+            .pick_relay_excluding(rng, WeightRole::Exit, &self.excluded, |r| {
+                self.ports_supported_by(r)
+            })
             .ok_or_else(|| Error::NoRelays("No exit relay found".into()))?;
 
         let middle = netdir
-            .pick_relay(rng, WeightRole::Middle, |r| !r.in_same_family(&exit))
+            .pick_relay_excluding(rng, WeightRole::Middle, &self.excluded, |r| {
+                !r.in_same_family(&exit)
+            })
             .ok_or_else(|| Error::NoRelays("No middle relay found".into()))?;
 
         let entry = netdir
-            .pick_relay(rng, WeightRole::Guard, |r| {
+            .pick_relay_excluding(rng, WeightRole::Guard, &self.excluded, |r| {
                 !r.in_same_family(&middle) && !r.in_same_family(&exit)
             })
             .ok_or_else(|| Error::NoRelays("No entry relay found".into()))?;
@@ -48,3 +63,30 @@ impl ExitPathBuilder {
         Ok(TorPath::Path(vec![entry, middle, exit]))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tor_netdir::testnet;
+
+    #[test]
+    fn avoids_excluded_relays() {
+        let netdir = testnet::netdir(&testnet::default_relays());
+        // Exclude every exit but the one at position 10, and a guard.
+        let excluded: Vec<_> = [0, 1, 4, 7].iter().map(|&i| testnet::rsa_id(i)).collect();
+        let mut builder = ExitPathBuilder::new(vec![TargetPort::ipv4(80)]);
+        builder.set_excluded_relays(&excluded);
+
+        let mut rng = rand::thread_rng();
+        for _ in 0..50 {
+            let path = builder.pick_path(&mut rng, (&netdir).into()).unwrap();
+            let relays = match path {
+                TorPath::Path(relays) => relays,
+                _ => panic!("exit path wasn't a multihop path"),
+            };
+            assert_eq!(relays.len(), 3);
+            assert_eq!(relays[2].rsa_id(), &testnet::rsa_id(10));
+            assert!(relays.iter().all(|r| !excluded.contains(r.rsa_id())));
+        }
+    }
+}
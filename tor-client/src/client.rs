@@ -3,14 +3,19 @@
 //! To construct a client, run the `TorClient::bootstrap()` method.
 //! Once the client is bootstrapped, you can make connections over the Tor
 //! network using `TorClient::connect()`.
+use crate::{BootstrapProgress, BootstrapStatus};
 use tor_circmgr::TargetPort;
-use tor_dirmgr::NetDirConfig;
+use tor_dirmgr::{DirMgrEvent, NetDirConfig};
 use tor_proto::circuit::IpVersionPreference;
 use tor_proto::stream::DataStream;
 use tor_rtcompat::{Runtime, SleepProviderExt};
 
-use std::sync::Arc;
+use futures::future::FutureExt;
+use futures::stream::{self, Stream, StreamExt};
+use futures::task::SpawnExt;
+use std::sync::{Arc, Weak};
 use std::time::Duration;
+use tokio_crate::sync::{broadcast, watch};
 
 use anyhow::{anyhow, Context, Result};
 use log::info;
@@ -31,6 +36,12 @@ pub struct TorClient<R: Runtime> {
     circmgr: Arc<tor_circmgr::CircMgr<R>>,
     /// Directory manager for keeping our directory material up to date.
     dirmgr: Arc<tor_dirmgr::DirMgr<R>>,
+    /// How ready we are to make connections.  A background task keeps
+    /// this up to date, and tells any subscribers when it changes.
+    status: Arc<watch::Sender<BootstrapStatus>>,
+    /// How much directory information we have.  The same background
+    /// task keeps this up to date.
+    progress: Arc<watch::Sender<BootstrapProgress>>,
 }
 
 /// Preferences for how to route a stream over the Tor network.
@@ -77,23 +88,199 @@ impl<R: Runtime> TorClient<R> {
     /// Return a client once there is enough directory material to
     /// connect safely over the Tor network.
     pub async fn bootstrap(runtime: R, dircfg: NetDirConfig) -> Result<TorClient<R>> {
-        let chanmgr = Arc::new(tor_chanmgr::ChanMgr::new(runtime.clone()));
-        let circmgr = Arc::new(tor_circmgr::CircMgr::new(
-            runtime.clone(),
-            Arc::clone(&chanmgr),
-        ));
         let dircfg_2 = dircfg.clone();
         let cache_path = dircfg_2.cache_path.to_str().unwrap();
         let dirmgr =
             tor_dirmgr::DirMgr::bootstrap_from_config(dircfg, runtime.clone(), cache_path).await?;
 
+        Self::with_dirmgr(runtime, dirmgr)
+    }
+
+    /// Return a client that uses `dirmgr`, an already-bootstrapped
+    /// directory manager, for its directory information.
+    ///
+    /// Several clients in the same process can share one directory
+    /// manager this way, so that only it downloads directory
+    /// information.  Each client still has its own channels and
+    /// circuits.
+    pub fn with_dirmgr(runtime: R, dirmgr: Arc<tor_dirmgr::DirMgr<R>>) -> Result<TorClient<R>> {
+        let chanmgr = Arc::new(tor_chanmgr::ChanMgr::new(runtime.clone()));
+        let mut circmgr = tor_circmgr::CircMgr::new(runtime.clone(), Arc::clone(&chanmgr));
+        circmgr.set_excluded_relays(dirmgr.config().excluded_relays());
+        let circmgr = Arc::new(circmgr);
+
+        let (initial, _) = Self::current_status(&dirmgr, None, &runtime);
+        let (progress, _) = watch::channel(Self::current_progress(&dirmgr, initial, None, false));
+        let (status, _) = watch::channel(initial);
+        let status = Arc::new(status);
+        let progress = Arc::new(progress);
+        runtime.spawn(Self::status_task(
+            Arc::downgrade(&status),
+            Arc::downgrade(&progress),
+            Arc::downgrade(&dirmgr),
+            dirmgr.subscribe(),
+            runtime.clone(),
+        ))?;
+
         Ok(TorClient {
             runtime,
             circmgr,
             dirmgr,
+            status,
+            progress,
         })
     }
 
+    /// Return how ready this client is to make connections.
+    pub fn bootstrap_status(&self) -> BootstrapStatus {
+        *self.status.borrow()
+    }
+
+    /// Return a stream of this client's bootstrap progress.
+    ///
+    /// The stream yields the current progress right away, and then a
+    /// new value whenever it changes: when we get a new consensus or
+    /// more microdescriptors, or when the client becomes ready or stops
+    /// being ready.
+    ///
+    /// Since [`TorClient::bootstrap`] only returns once it has a usable
+    /// directory, the first value from a new client is usually ready.
+    /// The stream ends once every TorClient sharing this progress has
+    /// been dropped.
+    pub fn bootstrap_events(&self) -> impl Stream<Item = BootstrapProgress> {
+        let mut rx = self.progress.subscribe();
+        let current = *rx.borrow_and_update();
+        let changes = stream::unfold(rx, |mut rx| async move {
+            rx.changed().await.ok()?;
+            let status = *rx.borrow_and_update();
+            Some((status, rx))
+        });
+        stream::once(async move { current }).chain(changes)
+    }
+
+    /// Return the bootstrap status that `dirmgr` is in, and how long
+    /// we can wait before checking it again.
+    ///
+    /// `progress` is the fraction of work done that `dirmgr` last
+    /// reported, if it has reported any.  It only matters while we
+    /// don't have a directory yet.
+    fn current_status(
+        dirmgr: &tor_dirmgr::DirMgr<R>,
+        progress: Option<f32>,
+        runtime: &R,
+    ) -> (BootstrapStatus, Duration) {
+        /// Longest time we'll wait between checks.
+        const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+        let netdir = match dirmgr.opt_netdir() {
+            Some(netdir) => netdir,
+            None => {
+                let status = match progress {
+                    Some(fraction) => BootstrapStatus::InProgress { fraction },
+                    None => BootstrapStatus::NotStarted,
+                };
+                return (status, MAX_CHECK_INTERVAL);
+            }
+        };
+        let valid_until = netdir.lifetime().valid_until();
+        match runtime.wallclock().duration_since(valid_until) {
+            Ok(age) => (BootstrapStatus::Stale { age }, MAX_CHECK_INTERVAL),
+            Err(e) => (BootstrapStatus::Ready, e.duration().min(MAX_CHECK_INTERVAL)),
+        }
+    }
+
+    /// Return how much directory information `dirmgr` has, given that
+    /// it is in `status`.
+    ///
+    /// `progress` is as for [`TorClient::current_status`].
+    /// `have_consensus` is true if `dirmgr` has told us about a new
+    /// consensus.
+    fn current_progress(
+        dirmgr: &tor_dirmgr::DirMgr<R>,
+        status: BootstrapStatus,
+        progress: Option<f32>,
+        have_consensus: bool,
+    ) -> BootstrapProgress {
+        let (fraction_dir, fraction_mds) = match dirmgr.opt_netdir() {
+            Some(netdir) => (1.0, netdir.fraction_available()),
+            None => {
+                let have_consensus = have_consensus || progress.is_some();
+                let fraction_dir = if have_consensus { 1.0 } else { 0.0 };
+                (fraction_dir, progress.map_or(0.0, f64::from))
+            }
+        };
+        BootstrapProgress {
+            fraction_dir,
+            fraction_mds,
+            is_ready: status.is_usable(),
+        }
+    }
+
+    /// Background task to keep `status` and `progress` up to date as `dirmgr`
+    /// bootstraps, and as its directory goes stale or gets replaced.
+    ///
+    /// We learn about progress and new directories from `events`, which
+    /// must come from [`tor_dirmgr::DirMgr::subscribe`].  This task
+    /// exits once every TorClient sharing `status` has been dropped, or
+    /// once `dirmgr` has been dropped.
+    async fn status_task(
+        status: Weak<watch::Sender<BootstrapStatus>>,
+        progress_tx: Weak<watch::Sender<BootstrapProgress>>,
+        dirmgr: Weak<tor_dirmgr::DirMgr<R>>,
+        mut events: broadcast::Receiver<DirMgrEvent>,
+        runtime: R,
+    ) {
+        // The fraction of work done that the dirmgr last reported.
+        let mut progress = None;
+        // True once the dirmgr has told us about a new consensus.
+        let mut have_consensus = false;
+        loop {
+            let (status, progress_tx, dirmgr) = match (
+                Weak::upgrade(&status),
+                Weak::upgrade(&progress_tx),
+                Weak::upgrade(&dirmgr),
+            ) {
+                (Some(s), Some(p), Some(d)) => (s, p, d),
+                (_, _, _) => return,
+            };
+            let (new_status, delay) = Self::current_status(&dirmgr, progress, &runtime);
+            let new_progress =
+                Self::current_progress(&dirmgr, new_status, progress, have_consensus);
+            // Only wake subscribers if we've moved to a different kind
+            // of status, or made progress bootstrapping.
+            status.send_if_modified(|s| {
+                let changed = match (*s, new_status) {
+                    (BootstrapStatus::Stale { .. }, BootstrapStatus::Stale { .. }) => false,
+                    (old, new) => old != new,
+                };
+                *s = new_status;
+                changed
+            });
+            progress_tx.send_if_modified(|p| {
+                let changed = *p != new_progress;
+                *p = new_progress;
+                changed
+            });
+            drop(status);
+            drop(progress_tx);
+            drop(dirmgr);
+
+            let sleep = runtime.sleep(delay).fuse();
+            let event = events.recv().fuse();
+            futures::pin_mut!(sleep, event);
+            futures::select! {
+                _ = sleep => {}
+                event = event => match event {
+                    Ok(DirMgrEvent::BootstrapProgress { fraction }) => progress = Some(fraction),
+                    Ok(DirMgrEvent::NewConsensus { .. }) => have_consensus = true,
+                    // On any other event, or if we missed some, we just
+                    // look at the directory again.
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            }
+        }
+    }
+
     /// Launch a connection to the provided address and port over the Tor
     /// network.
     ///
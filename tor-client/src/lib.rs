@@ -17,5 +17,7 @@
 #![warn(clippy::rc_buffer)]
 
 mod client;
+mod status;
 
 pub use client::{ConnectPrefs, TorClient};
+pub use status::{BootstrapProgress, BootstrapStatus};
@@ -0,0 +1,54 @@
+//! Report on how far along we are in bootstrapping.
+
+use std::time::Duration;
+
+/// How ready a [`TorClient`](crate::TorClient) is to make connections.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum BootstrapStatus {
+    /// We haven't started bootstrapping.
+    NotStarted,
+    /// We're bootstrapping, and have done about `fraction` (from 0.0
+    /// to 1.0) of the work.
+    InProgress {
+        /// Roughly how much of the work we've done.
+        fraction: f32,
+    },
+    /// We have a usable, up-to-date directory.
+    Ready,
+    /// We have a directory, but it expired `age` ago.
+    ///
+    /// We can still try to use it, but we should be fetching a
+    /// replacement.
+    Stale {
+        /// How long ago our directory expired.
+        age: Duration,
+    },
+}
+
+impl BootstrapStatus {
+    /// Return true if the client can make connections, even if its
+    /// directory may be out of date.
+    pub fn is_usable(&self) -> bool {
+        matches!(self, BootstrapStatus::Ready | BootstrapStatus::Stale { .. })
+    }
+}
+
+/// How much of the directory information it needs a
+/// [`TorClient`](crate::TorClient) has, as reported by
+/// [`TorClient::bootstrap_events`](crate::TorClient::bootstrap_events).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub struct BootstrapProgress {
+    /// The fraction (from 0.0 to 1.0) of the consensus that we have.
+    ///
+    /// We read the consensus in one piece, so for now this is always
+    /// 0.0 or 1.0.
+    pub fraction_dir: f64,
+    /// The fraction (from 0.0 to 1.0) of the consensus bandwidth on
+    /// relays whose microdescriptors we have.
+    pub fraction_mds: f64,
+    /// True if the client can make connections.  (See
+    /// [`BootstrapStatus::is_usable`].)
+    pub is_ready: bool,
+}
@@ -46,6 +46,10 @@ pub enum Error {
     /// Unrecognized content-encoding
     #[error("Unrecognized content encoding: {0:?}")]
     ContentEncoding(String),
+
+    /// Got a content-type other than text/plain or application/octet-stream
+    #[error("Unexpected content type: {0:?}")]
+    UnexpectedContentType(String),
 }
 
 impl From<TimeoutError> for Error {
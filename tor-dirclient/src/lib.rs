@@ -140,6 +140,11 @@ where
     if header.status != Some(200) {
         return Err(Error::HttpStatus(header.status));
     }
+    if let Some(ctype) = header.content_type {
+        if !content_type_ok(&ctype) {
+            return Err(Error::UnexpectedContentType(ctype));
+        }
+    }
 
     let mut decoder = get_decoder(buffered, header.encoding.as_deref())?;
 
@@ -198,6 +203,7 @@ where
                     return Ok(HeaderStatus {
                         status: response.code,
                         encoding: None,
+                        content_type: None,
                     });
                 }
                 let encoding = if let Some(enc) = response
@@ -209,6 +215,15 @@ where
                 } else {
                     None
                 };
+                let content_type = if let Some(ctype) = response
+                    .headers
+                    .iter()
+                    .find(|h| h.name.eq_ignore_ascii_case("Content-Type"))
+                {
+                    Some(String::from_utf8(ctype.value.to_vec())?)
+                } else {
+                    None
+                };
                 /*
                 if let Some(clen) = response.headers.iter().find(|h| h.name == "Content-Length") {
                     let clen = std::str::from_utf8(clen.value)?;
@@ -219,6 +234,7 @@ where
                 return Ok(HeaderStatus {
                     status: Some(200),
                     encoding,
+                    content_type,
                 });
             }
         }
@@ -235,6 +251,18 @@ struct HeaderStatus {
     status: Option<u16>,
     /// The Content-Encoding header, if any.
     encoding: Option<String>,
+    /// The Content-Type header, if any.
+    content_type: Option<String>,
+}
+
+/// Return true if `ctype` is a Content-Type that a directory cache
+/// might use for the documents we ask for.
+///
+/// Any parameters (like `; charset=...`) are ignored.
+fn content_type_ok(ctype: &str) -> bool {
+    let media_type = ctype.split(';').next().unwrap_or("").trim();
+    media_type.eq_ignore_ascii_case("text/plain")
+        || media_type.eq_ignore_ascii_case("application/octet-stream")
 }
 
 /// Helper: download directory information from `stream` and
@@ -503,6 +531,13 @@ mod test {
 
         assert_eq!(h.status, Some(200));
         assert_eq!(h.encoding.as_deref(), Some("Waffles"));
+        assert!(h.content_type.is_none());
+
+        // now try with a content type.
+        let text = b"HTTP/1.0 200 OK\r\nContent-Type: text/html\r\n\r\n";
+        let mut s = &text[..];
+        let h = read_headers(&mut s).await?;
+        assert_eq!(h.content_type.as_deref(), Some("text/html"));
 
         // now try truncated
         let mut s = &text[..15];
@@ -520,6 +555,15 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn content_types() {
+        assert!(content_type_ok("text/plain"));
+        assert!(content_type_ok("Text/Plain; charset=utf-8"));
+        assert!(content_type_ok("application/octet-stream"));
+        assert!(!content_type_ok("text/html"));
+        assert!(!content_type_ok(""));
+    }
+
     #[async_test]
     async fn headers_bogus() -> Result<()> {
         let text = b"HTTP/999.0 WHAT EVEN\r\n\r\n";
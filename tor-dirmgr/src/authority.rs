@@ -8,6 +8,7 @@
 use anyhow::{bail, Context, Error, Result};
 use serde::Deserialize;
 use std::str::FromStr;
+use std::time::SystemTime;
 use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdoc::doc::authcert::{AuthCert, AuthCertKeyIds};
 
@@ -31,6 +32,10 @@ impl Authority {
     pub fn new(name: String, v3ident: RsaIdentity) -> Self {
         Authority { name, v3ident }
     }
+    /// Return the nickname of this authority.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
     /// Return the v3 identity key of this certificate.
     pub fn v3ident(&self) -> &RsaIdentity {
         &self.v3ident
@@ -76,6 +81,75 @@ impl FromStr for Authority {
     }
 }
 
+/// Statistics about our attempts to fetch certificates for a single
+/// authority.
+///
+/// These are meant for diagnosing authorities that aren't responding:
+/// if a consensus doesn't have enough signatures we can use, they tell
+/// us whose certificates we failed to get, and why.
+#[derive(Debug, Clone)]
+pub struct AuthorityDownloadStats {
+    /// The nickname of the authority.
+    authority_name: String,
+    /// The v3 identity of the authority.
+    v3ident: RsaIdentity,
+    /// How many usable certificates we have fetched for this authority.
+    certs_fetched: u32,
+    /// A description of the last error we got when fetching a
+    /// certificate for this authority, if any.
+    last_error: Option<String>,
+    /// The last time we successfully fetched a certificate for this
+    /// authority, if ever.
+    last_success: Option<SystemTime>,
+}
+
+impl AuthorityDownloadStats {
+    /// Construct a new empty set of statistics for `authority`.
+    pub(crate) fn new(authority: &Authority) -> Self {
+        AuthorityDownloadStats {
+            authority_name: authority.name.clone(),
+            v3ident: authority.v3ident,
+            certs_fetched: 0,
+            last_error: None,
+            last_success: None,
+        }
+    }
+    /// Return the nickname of the authority these statistics are for.
+    pub fn authority_name(&self) -> &str {
+        &self.authority_name
+    }
+    /// Return the v3 identity of the authority these statistics are for.
+    pub fn v3ident(&self) -> &RsaIdentity {
+        &self.v3ident
+    }
+    /// Return the number of usable certificates we have fetched for
+    /// this authority.
+    pub fn certs_fetched(&self) -> u32 {
+        self.certs_fetched
+    }
+    /// Return a description of the last error we got when fetching a
+    /// certificate for this authority, if any.
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+    /// Return the last time we successfully fetched a certificate for
+    /// this authority, if ever.
+    pub fn last_success(&self) -> Option<SystemTime> {
+        self.last_success
+    }
+    /// Record the outcome of an attempt to fetch a certificate for
+    /// this authority.
+    pub(crate) fn note_attempt(&mut self, outcome: std::result::Result<(), String>) {
+        match outcome {
+            Ok(()) => {
+                self.certs_fetched = self.certs_fetched.saturating_add(1);
+                self.last_success = Some(SystemTime::now());
+            }
+            Err(e) => self.last_error = Some(e),
+        }
+    }
+}
+
 /// Return a vector of the default directory authorities.
 pub(crate) fn default_authorities() -> Vec<Authority> {
     /// Build an authority; panic if input is bad.
@@ -3,7 +3,73 @@
 
 // Code mostly copied from Arti.
 
-use crate::{DirState, Result};
+use crate::{DirState, Error, Result};
+
+use log::info;
+use std::time::Instant;
+
+/// A phase of the directory bootstrapping process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BootstrapPhase {
+    /// We are looking for a consensus document.
+    FetchingConsensus,
+    /// We have a consensus, and are looking for the authority
+    /// certificates to validate it.
+    FetchingCerts,
+    /// We have a validated consensus, and are looking for the
+    /// microdescriptors it lists.
+    FetchingMicrodescs {
+        /// How many microdescriptors we have so far.
+        fetched: usize,
+        /// How many microdescriptors the consensus lists.
+        total: usize,
+    },
+    /// We have every document we need.
+    Complete,
+}
+
+impl BootstrapPhase {
+    /// Return true if `self` and `other` are the same phase, ignoring
+    /// any progress counts.
+    fn same_phase(&self, other: &BootstrapPhase) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other)
+    }
+}
+
+/// Tracks the current bootstrap phase, and logs each transition between
+/// phases along with how long the previous phase took.
+struct PhaseTracker {
+    /// The phase we are currently in.
+    phase: BootstrapPhase,
+    /// The time at which we entered `phase`.
+    started: Instant,
+}
+
+impl PhaseTracker {
+    /// Start tracking from the phase of `state`.
+    fn new(state: &dyn DirState) -> Self {
+        PhaseTracker {
+            phase: state.bootstrap_phase(),
+            started: Instant::now(),
+        }
+    }
+
+    /// Note the current phase of `state`, logging if it has changed.
+    fn update(&mut self, state: &dyn DirState) {
+        let phase = state.bootstrap_phase();
+        if !phase.same_phase(&self.phase) {
+            let now = Instant::now();
+            let duration_ms = now.duration_since(self.started).as_millis();
+            info!(
+                "Bootstrap phase {:?} finished: duration_ms={}; now in {:?}",
+                self.phase, duration_ms, phase
+            );
+            self.started = now;
+        }
+        self.phase = phase;
+    }
+}
 
 /// Try tp update `state` by loading cached information from `dirmgr`.
 /// Return true if anything changed.
@@ -20,13 +86,19 @@ async fn load_once(state: &mut Box<dyn DirState>, docdir: &str) -> Result<bool>
 /// cache in `dirmgr`, advancing the state to the extent possible.
 ///
 /// No downloads are performed; the provided state will not be reset.
+///
+/// If the cache keeps changing the state without ever letting it
+/// advance, give up with [`Error::CantAdvanceState`].
 pub(crate) async fn load(mut state: Box<dyn DirState>, docdir: &str) -> Result<Box<dyn DirState>> {
     let mut safety_counter = 0_usize;
+    let mut tracker = PhaseTracker::new(state.as_ref());
     loop {
         let changed = load_once(&mut state, &docdir).await?;
+        tracker.update(state.as_ref());
 
         if state.can_advance() {
             state = state.advance()?;
+            tracker.update(state.as_ref());
             safety_counter = 0;
         } else {
             if !changed {
@@ -34,10 +106,62 @@ pub(crate) async fn load(mut state: Box<dyn DirState>, docdir: &str) -> Result<B
             }
             safety_counter += 1;
             if safety_counter == 100 {
-                panic!("Spent 100 iterations in the same state: this is a bug");
+                return Err(Error::CantAdvanceState(tracker.phase).into());
             }
         }
     }
 
     Ok(state)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::DocId;
+    use std::time::SystemTime;
+    use tor_netdoc::doc::netstatus::ConsensusFlavor;
+
+    /// A state that finds something new in the cache every time, but
+    /// never has enough to advance.
+    struct StuckState;
+
+    impl DirState for StuckState {
+        fn bootstrap_phase(&self) -> BootstrapPhase {
+            BootstrapPhase::FetchingCerts
+        }
+        fn describe(&self) -> String {
+            "stuck".into()
+        }
+        fn missing_docs(&self) -> Vec<DocId> {
+            vec![DocId::LatestConsensus {
+                flavor: ConsensusFlavor::Microdesc,
+                cache_usage: crate::CacheUsage::CacheOnly,
+            }]
+        }
+        fn can_advance(&self) -> bool {
+            false
+        }
+        fn add_from_cache(&mut self, _docdir: &str) -> Result<bool> {
+            Ok(true)
+        }
+        fn advance(self: Box<Self>) -> Result<Box<dyn DirState>> {
+            Ok(self)
+        }
+        fn reset_time(&self) -> Option<SystemTime> {
+            None
+        }
+        fn reset(self: Box<Self>) -> Result<Box<dyn DirState>> {
+            Ok(self)
+        }
+    }
+
+    #[test]
+    fn stuck_state() {
+        let result = futures::executor::block_on(load(Box::new(StuckState), "/nonexistent"));
+        let err = result.err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::CantAdvanceState(BootstrapPhase::FetchingCerts))
+        ));
+    }
+}
@@ -5,13 +5,16 @@
 
 // Code mostly copied from Arti.
 
+use crate::docmeta::ConsensusSource;
 use crate::retry::RetryConfig;
 use crate::Authority;
 use crate::{Error, Result};
+use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdir::fallback::FallbackDir;
 use tor_netdoc::doc::netstatus;
 
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use serde::Deserialize;
 
@@ -38,6 +41,19 @@ pub struct NetworkConfig {
     /// setttings in the consensus.
     #[serde(default)]
     override_net_params: netstatus::NetParams<i32>,
+
+    /// A list of relays that we should never pick when choosing relays
+    /// at random, identified by their hex-encoded RSA identities.
+    #[serde(default)]
+    excluded_relays: Vec<RsaIdentity>,
+
+    /// If set, the fraction of our authorities that must sign a
+    /// consensus for us to accept it, instead of the usual "more than
+    /// half".  Must be greater than 0 and no more than 1.
+    ///
+    /// This is meant for private networks with few authorities.
+    #[serde(default)]
+    consensus_signature_threshold: Option<f64>,
 }
 
 impl Default for NetworkConfig {
@@ -46,6 +62,8 @@ impl Default for NetworkConfig {
             fallback_cache: fallbacks::default_fallbacks(),
             authority: crate::authority::default_authorities(),
             override_net_params: Default::default(),
+            excluded_relays: Vec::new(),
+            consensus_signature_threshold: None,
         }
     }
 }
@@ -74,6 +92,17 @@ pub struct DownloadScheduleConfig {
     /// Number of microdescriptor downloads to attempt in parallel
     #[serde(default = "default_microdesc_parallelism")]
     microdesc_parallelism: u8,
+
+    /// How long before our consensus stops being fresh should we start
+    /// trying to replace it?
+    ///
+    /// We never start before halfway through the consensus's fresh
+    /// interval, however long this is.
+    #[serde(
+        default = "default_prefetch_consensus_before_expiry",
+        with = "humantime_serde"
+    )]
+    prefetch_consensus_before_expiry: Duration,
 }
 
 /// Default value for retry_bootstrap in DownloadScheduleConfig.
@@ -84,6 +113,11 @@ fn default_retry_bootstrap() -> RetryConfig {
 fn default_microdesc_parallelism() -> u8 {
     4
 }
+/// Default value for prefetch_consensus_before_expiry in
+/// DownloadScheduleConfig.
+fn default_prefetch_consensus_before_expiry() -> Duration {
+    Duration::from_secs(3 * 60 * 60)
+}
 
 impl Default for DownloadScheduleConfig {
     fn default() -> Self {
@@ -93,6 +127,7 @@ impl Default for DownloadScheduleConfig {
             retry_certs: Default::default(),
             retry_microdescs: Default::default(),
             microdesc_parallelism: default_microdesc_parallelism(),
+            prefetch_consensus_before_expiry: default_prefetch_consensus_before_expiry(),
         }
     }
 }
@@ -229,6 +264,14 @@ impl NetDirConfigBuilder {
         if self.network.fallback_cache.is_empty() {
             return Err(Error::BadNetworkConfig("No fallback caches configured").into());
         }
+        if let Some(t) = self.network.consensus_signature_threshold {
+            if !(t > 0.0 && t <= 1.0) {
+                return Err(Error::BadNetworkConfig(
+                    "consensus_signature_threshold must be in (0, 1]",
+                )
+                .into());
+            }
+        }
 
         Ok(NetDirConfig {
             legacy_cache_path: self.legacy_cache_path,
@@ -266,11 +309,47 @@ impl NetDirConfig {
         &self.network.override_net_params
     }
 
+    /// Return the list of relays that we should never pick at random.
+    ///
+    /// `TorClient` passes this list to its circuit manager, which
+    /// leaves these relays (and fallbacks) out of every path it builds.
+    pub fn excluded_relays(&self) -> &[RsaIdentity] {
+        &self.network.excluded_relays[..]
+    }
+
+    /// Return the configured fraction of authorities that must sign a
+    /// consensus, if it overrides the default.
+    pub fn consensus_signature_threshold(&self) -> Option<f64> {
+        self.network.consensus_signature_threshold
+    }
+
+    /// Return the number of our authorities whose signatures a consensus
+    /// needs before we accept it.
+    pub(crate) fn n_signatures_needed(&self) -> usize {
+        let n_authorities = self.authorities().len();
+        match self.consensus_signature_threshold() {
+            Some(frac) => {
+                let needed = (frac * n_authorities as f64).ceil() as usize;
+                needed.clamp(1, n_authorities.max(1))
+            }
+            None => n_authorities / 2 + 1,
+        }
+    }
+
     /// Return the timing configuration we should use to decide when to
     /// attemppt and retry downloads.
     pub fn timing(&self) -> &DownloadScheduleConfig {
         &self.timing
     }
+
+    /// Return where we got `consensus` from.
+    ///
+    /// For now, we only read consensus documents from the files in
+    /// our cache directory, so this is always
+    /// [`ConsensusSource::LegacyCache`].
+    pub fn consensus_source(&self, _consensus: &netstatus::MdConsensus) -> ConsensusSource {
+        ConsensusSource::LegacyCache
+    }
 }
 
 impl DownloadScheduleConfig {
@@ -299,6 +378,35 @@ impl DownloadScheduleConfig {
     pub fn microdesc_parallelism(&self) -> usize {
         self.microdesc_parallelism.max(1).into()
     }
+
+    /// Return how long before our consensus stops being fresh we should
+    /// start trying to replace it.
+    pub fn prefetch_consensus_before_expiry(&self) -> Duration {
+        self.prefetch_consensus_before_expiry
+    }
+
+    /// Never wait longer than `max` between attempts at bootstrapping.
+    pub fn set_retry_bootstrap_max_delay(&mut self, max: Duration) {
+        self.retry_bootstrap = self.retry_bootstrap.with_max_delay(max);
+    }
+
+    /// Never wait longer than `max` between attempts to download a
+    /// consensus.
+    pub fn set_retry_consensus_max_delay(&mut self, max: Duration) {
+        self.retry_consensus = self.retry_consensus.with_max_delay(max);
+    }
+
+    /// Never wait longer than `max` between attempts to download
+    /// authority certificates.
+    pub fn set_retry_certs_max_delay(&mut self, max: Duration) {
+        self.retry_certs = self.retry_certs.with_max_delay(max);
+    }
+
+    /// Never wait longer than `max` between attempts to download
+    /// microdescriptors.
+    pub fn set_retry_microdescs_max_delay(&mut self, max: Duration) {
+        self.retry_microdescs = self.retry_microdescs.with_max_delay(max);
+    }
 }
 
 /// Helpers for fallbacksx
@@ -7,6 +7,9 @@ use tor_netdoc::doc::{
     authcert::AuthCertKeyIds, microdesc::MdDigest, netstatus::ConsensusFlavor, routerdesc::RdDigest,
 };
 
+#[cfg(feature = "hs-client")]
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
+
 /// The identity of a single document, in enough detail to load it
 /// from storage.
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
@@ -26,10 +29,25 @@ pub enum DocId {
     Microdesc(MdDigest),
     /// A request for a router descriptor, by SHA1 digest.
     Routerdesc(RdDigest),
+    /// A request for a v3 onion service descriptor, by blinded identity
+    /// and revision counter.
+    ///
+    /// (There is deliberately no variant for v2 onion service
+    /// descriptors: v2 onion services have been removed from Tor, and
+    /// HSDirs no longer serve their descriptors.)
+    #[cfg(feature = "hs-client")]
+    HsDesc {
+        /// The blinded Ed25519 identity key of the onion service for the
+        /// current time period.
+        blinded_id: Ed25519Identity,
+        /// The revision counter of the descriptor we want.
+        revision_counter: u64,
+    },
 }
 
 /// Description of how to start out a given bootstrap attempt.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[non_exhaustive]
 pub enum CacheUsage {
     /// The bootstrap attempt will only use the cache.  Therefore, don't
     /// load a pending consensus from the cache, since we won't be able
@@ -42,14 +60,26 @@ pub enum CacheUsage {
     /// The bootstrap attempt is trying to fetch a new consensus. Therefore,
     /// we don't want a consensus from the cache.
     MustDownload,
+    /// The bootstrap attempt will only use the cache, and will accept a
+    /// cached consensus even if it has expired.  (A consensus that
+    /// isn't valid yet is still rejected.)
+    ///
+    /// This is for clients that are offline, and would rather have an
+    /// old directory than none at all.  Callers can tell whether they
+    /// got an expired directory from its lifetime.
+    Stale,
 }
 
 /// A group of DocIds that can be downloaded or loaded from the database
 /// together.
 ///
+/// Code that implements its own downloader can use this type to batch
+/// its requests the same way we do.
+///
 /// TODO: Perhaps this should be the same as ClientRequest?
 #[derive(Clone, Debug)]
-pub(crate) enum DocQuery {
+#[non_exhaustive]
+pub enum DocQuery {
     /// A request for the lastet consensus
     LatestConsensus {
         /// A desired flavor of consenus
@@ -63,6 +93,10 @@ pub(crate) enum DocQuery {
     Microdesc(Vec<MdDigest>),
     /// A request for router descriptors
     Routerdesc(Vec<RdDigest>),
+    /// A request for onion service descriptors, by blinded identity and
+    /// revision counter.
+    #[cfg(feature = "hs-client")]
+    HsDesc(Vec<(Ed25519Identity, u64)>),
 }
 
 impl DocQuery {
@@ -79,19 +113,120 @@ impl DocQuery {
             DocId::AuthCert(_) => Self::AuthCert(Vec::new()),
             DocId::Microdesc(_) => Self::Microdesc(Vec::new()),
             DocId::Routerdesc(_) => Self::Routerdesc(Vec::new()),
+            #[cfg(feature = "hs-client")]
+            DocId::HsDesc { .. } => Self::HsDesc(Vec::new()),
+        }
+    }
+
+    /// Remove any repeated entries from this query, keeping the first
+    /// copy of each.
+    ///
+    /// The same document can be listed more than once if (for example)
+    /// the consensus changed while we were building a batch.  Call
+    /// this before downloading a batch, so that we don't ask for the
+    /// same document twice.
+    pub fn deduplicate(&mut self) {
+        /// Remove every element of `v` that is equal to an earlier
+        /// element, without otherwise changing the order.
+        fn dedup_in_order<T: Copy + Eq + std::hash::Hash>(v: &mut Vec<T>) {
+            let mut seen = std::collections::HashSet::new();
+            v.retain(|x| seen.insert(*x));
+        }
+        match self {
+            Self::LatestConsensus { .. } => {}
+            Self::AuthCert(ids) => dedup_in_order(ids),
+            Self::Microdesc(ids) => dedup_in_order(ids),
+            Self::Routerdesc(ids) => dedup_in_order(ids),
+            #[cfg(feature = "hs-client")]
+            Self::HsDesc(ids) => dedup_in_order(ids),
         }
     }
 
-    /// Add `id` to this query, if possible.
-    fn push(&mut self, id: DocId) {
+    /// Add `id` to this query.
+    ///
+    /// Adding a consensus request to a consensus query has no effect.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` is not the same kind of document as the ones this
+    /// query is for.
+    pub fn push(&mut self, id: DocId) {
         match (self, id) {
             (Self::LatestConsensus { .. }, DocId::LatestConsensus { .. }) => {}
             (Self::AuthCert(ids), DocId::AuthCert(id)) => ids.push(id),
             (Self::Microdesc(ids), DocId::Microdesc(id)) => ids.push(id),
             (Self::Routerdesc(ids), DocId::Routerdesc(id)) => ids.push(id),
-            (_, _) => panic!(),
+            #[cfg(feature = "hs-client")]
+            (
+                Self::HsDesc(ids),
+                DocId::HsDesc {
+                    blinded_id,
+                    revision_counter,
+                },
+            ) => ids.push((blinded_id, revision_counter)),
+            (_, _) => panic!("Tried to add a DocId to a query of a different type"),
         }
     }
+
+    /// Add every member of `ids` to this query.
+    ///
+    /// # Panics
+    ///
+    /// As with [`DocQuery::push`], panics if any member of `ids` is not
+    /// the same kind of document as the ones this query is for.
+    pub fn extend<I: IntoIterator<Item = DocId>>(&mut self, ids: I) {
+        for id in ids {
+            self.push(id);
+        }
+    }
+
+    /// Return the number of documents that this query is for.
+    ///
+    /// A consensus query is always for exactly one document.
+    pub fn len(&self) -> usize {
+        match self {
+            Self::LatestConsensus { .. } => 1,
+            Self::AuthCert(ids) => ids.len(),
+            Self::Microdesc(ids) => ids.len(),
+            Self::Routerdesc(ids) => ids.len(),
+            #[cfg(feature = "hs-client")]
+            Self::HsDesc(ids) => ids.len(),
+        }
+    }
+
+    /// Return true if this query is for no documents at all.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl IntoIterator for DocQuery {
+    type Item = DocId;
+    type IntoIter = std::vec::IntoIter<DocId>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let ids: Vec<DocId> = match self {
+            Self::LatestConsensus {
+                flavor,
+                cache_usage,
+            } => vec![DocId::LatestConsensus {
+                flavor,
+                cache_usage,
+            }],
+            Self::AuthCert(ids) => ids.into_iter().map(DocId::AuthCert).collect(),
+            Self::Microdesc(ids) => ids.into_iter().map(DocId::Microdesc).collect(),
+            Self::Routerdesc(ids) => ids.into_iter().map(DocId::Routerdesc).collect(),
+            #[cfg(feature = "hs-client")]
+            Self::HsDesc(ids) => ids
+                .into_iter()
+                .map(|(blinded_id, revision_counter)| DocId::HsDesc {
+                    blinded_id,
+                    revision_counter,
+                })
+                .collect(),
+        };
+        ids.into_iter()
+    }
 }
 
 impl From<DocId> for DocQuery {
@@ -101,3 +236,44 @@ impl From<DocId> for DocQuery {
         result
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deduplicate() {
+        let mut q = DocQuery::Microdesc(vec![[3; 32], [1; 32], [3; 32], [2; 32], [1; 32]]);
+        q.deduplicate();
+        match q {
+            DocQuery::Microdesc(ids) => assert_eq!(ids, vec![[3; 32], [1; 32], [2; 32]]),
+            _ => panic!("wrong query type"),
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let mds = [DocId::Microdesc([1; 32]), DocId::Microdesc([2; 32])];
+        let mut q = DocQuery::empty_from_docid(&mds[0]);
+        assert!(q.is_empty());
+        q.extend(mds.iter().copied());
+        assert_eq!(q.len(), 2);
+        let ids: Vec<DocId> = q.into_iter().collect();
+        assert_eq!(&ids[..], &mds[..]);
+
+        let cons = DocId::LatestConsensus {
+            flavor: ConsensusFlavor::Microdesc,
+            cache_usage: CacheUsage::CacheOkay,
+        };
+        let q: DocQuery = cons.into();
+        assert_eq!(q.len(), 1);
+        assert_eq!(q.into_iter().collect::<Vec<_>>(), vec![cons]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn push_wrong_type() {
+        let mut q = DocQuery::Microdesc(Vec::new());
+        q.push(DocId::Routerdesc([0; 20]));
+    }
+}
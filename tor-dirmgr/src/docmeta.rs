@@ -65,6 +65,22 @@ impl ConsensusMeta {
     }
 }
 
+/// Where we got a consensus from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConsensusSource {
+    /// We read the consensus from the files in our cache directory.
+    LegacyCache,
+}
+
+impl std::fmt::Display for ConsensusSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConsensusSource::LegacyCache => write!(f, "cache"),
+        }
+    }
+}
+
 /// Compute the sha3-256 digests of signed_part on its own, and of
 /// signed_part concatenated with remainder.
 fn sha3_dual(signed_part: impl AsRef<[u8]>, remainder: impl AsRef<[u8]>) -> ([u8; 32], [u8; 32]) {
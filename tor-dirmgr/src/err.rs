@@ -2,6 +2,8 @@
 
 // Code mostly copied from Arti.
 
+use crate::BootstrapPhase;
+use std::time::SystemTime;
 use thiserror::Error;
 
 /// An error originated by the directory manager code
@@ -37,17 +39,39 @@ pub enum Error {
     /// bootstrapped directory, but we didn't have one.
     #[error("directory not present or not up-to-date")]
     DirectoryNotPresent,
+    /// We had no usable directory, and the only consensus we could
+    /// find had expired.
+    ///
+    /// Callers that can live with old information can try again with
+    /// [`CacheUsage::Stale`](crate::CacheUsage::Stale).
+    #[error("cached consensus expired at {}", chrono::DateTime::<chrono::Utc>::from(*expired_at))]
+    ExpiredConsensus {
+        /// When the consensus stopped being valid.
+        expired_at: SystemTime,
+    },
     /// Another process has locked the store for writing.
     #[error("couldn't get write lock on directory cache")]
     CacheIsLocked,
+    /// An authority certificate had a version that we don't support.
+    #[error("unsupported authority certificate version {0}")]
+    UnsupportedCertVersion(u32),
     /// A consensus document is signed by an unrecognized authority set.
     #[error("authorities on consensus do not match what we expect.")]
     UnrecognizedAuthorities,
+    /// A consensus document is signed by some of the authorities we
+    /// expect, but not by enough of them.
+    #[error("consensus had only {got} of the {needed} authority signatures we need")]
+    AuthorityConsensusThreshold {
+        /// How many of our authorities signed the consensus.
+        got: usize,
+        /// How many of our authorities need to have signed it.
+        needed: usize,
+    },
     /// A directory manager has been dropped; background tasks can exit too.
     #[error("dirmgr has been dropped; background tasks exiting")]
     ManagerDropped,
     /// We made a bunch of attempts, but weren't unable to advance the
-    /// state of a download.
-    #[error("unable to finish bootstrapping a directory")]
-    CantAdvanceState,
+    /// state of a download.  Carries the last phase we reached.
+    #[error("unable to finish bootstrapping a directory: stuck in {0:?}")]
+    CantAdvanceState(BootstrapPhase),
 }
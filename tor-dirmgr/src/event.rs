@@ -0,0 +1,28 @@
+//! Events that a [`DirMgr`](crate::DirMgr) sends to its subscribers.
+
+use std::time::SystemTime;
+
+/// A significant change in the state of a directory manager.
+///
+/// See [`DirMgr::subscribe`](crate::DirMgr::subscribe).
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[non_exhaustive]
+pub enum DirMgrEvent {
+    /// We have accepted a new consensus, and are about to look for the
+    /// microdescriptors it lists.
+    NewConsensus {
+        /// The `valid-after` time of the new consensus.
+        valid_after: SystemTime,
+    },
+    /// We have a new or updated directory that is usable for building
+    /// circuits.
+    NewNetDir,
+    /// We have made progress fetching the microdescriptors for a
+    /// consensus that isn't usable yet.
+    BootstrapProgress {
+        /// The fraction of the consensus bandwidth on relays whose
+        /// microdescriptors we have, between 0.0 and 1.0.  (See
+        /// [`tor_netdir::PartialNetDir::fraction_available`].)
+        fraction: f32,
+    },
+}
@@ -25,23 +25,33 @@ mod config;
 mod docid;
 mod docmeta;
 mod err;
+mod event;
 mod retry;
 mod shared_ref;
 mod state;
 
-use std::{sync::Arc, time::SystemTime};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex, Weak},
+    time::{Duration, SystemTime},
+};
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
-pub use authority::Authority;
+pub use authority::{Authority, AuthorityDownloadStats};
+pub use bootstrap::BootstrapPhase;
 pub use config::{DownloadScheduleConfig, NetDirConfig, NetDirConfigBuilder, NetworkConfig};
-pub use docid::DocId;
+pub use docid::{CacheUsage, DocId, DocQuery};
+pub use docmeta::ConsensusSource;
 pub use err::Error;
-use log::{info, warn};
+pub use event::DirMgrEvent;
+use futures::task::SpawnExt;
+use log::{debug, info, warn};
+use tokio_crate::sync::{broadcast, watch};
 use tor_netdir::NetDir;
-use tor_rtcompat::Runtime;
+use tor_rtcompat::{Runtime, SleepProviderExt};
 
-use crate::{docid::CacheUsage, shared_ref::SharedMutArc};
+use crate::shared_ref::SharedMutArc;
 
 /// A directory manager to download, fetch, and cache a Tor directory.
 ///
@@ -61,7 +71,9 @@ use crate::{docid::CacheUsage, shared_ref::SharedMutArc};
 ///
 /// Because of portability issues in [`fslock::LockFile`], you might
 /// get weird results if you run two of these in the same process with
-/// the same underlying cache.
+/// the same underlying cache.  Instead, share a single `Arc<DirMgr>`
+/// between the users in a process: for example, with
+/// `TorClient::with_dirmgr`.
 pub struct DirMgr<R: Runtime> {
     /// Configuration information: where to find directories, how to
     /// validate them, and so on.
@@ -73,10 +85,86 @@ pub struct DirMgr<R: Runtime> {
     /// users, and replace it once a new directory is bootstrapped.
     netdir: SharedMutArc<NetDir>,
 
+    /// Statistics about our attempts to fetch certificates from each
+    /// configured authority.
+    authority_stats: Mutex<Vec<AuthorityDownloadStats>>,
+
+    /// Sender to tell watchers when our current consensus expires.
+    expiry_tx: watch::Sender<SystemTime>,
+
+    /// Receiver to clone for new watchers of our consensus expiry.
+    ///
+    /// (We keep one of these around so that sending never fails for
+    /// lack of receivers.)
+    expiry_rx: watch::Receiver<SystemTime>,
+
+    /// Sender to tell subscribers about changes in our state.
+    event_tx: broadcast::Sender<DirMgrEvent>,
+
+    /// Sender to tell our background tasks that we are shutting down.
+    ///
+    /// This is shared with every [`DirMgrHandle`].
+    shutdown_tx: Arc<watch::Sender<bool>>,
+
+    /// Receiver to clone for background tasks that need to notice
+    /// when we shut down.
+    shutdown_rx: watch::Receiver<bool>,
+
+    /// A lock that every directory load holds for reading, so that
+    /// [`DirMgrHandle::shutdown`] can wait for loads in progress.
+    loads_in_progress: Arc<tokio_crate::sync::RwLock<()>>,
+
     /// Our asynchronous runtime.
     runtime: R,
 }
 
+/// A handle that can shut down a [`DirMgr`], even when the DirMgr
+/// itself is shared behind an `Arc`.
+///
+/// Get one from [`DirMgr::shutdown_handle`].
+#[derive(Clone)]
+pub struct DirMgrHandle<R: Runtime> {
+    /// Sender to tell the DirMgr's background tasks that it is
+    /// shutting down.
+    shutdown_tx: Arc<watch::Sender<bool>>,
+
+    /// The DirMgr's lock for directory loads in progress.
+    loads_in_progress: Arc<tokio_crate::sync::RwLock<()>>,
+
+    /// The DirMgr's asynchronous runtime.
+    runtime: R,
+}
+
+impl<R: Runtime> DirMgrHandle<R> {
+    /// Stop the directory manager's background tasks, and wait for
+    /// any directory load in progress to finish.
+    ///
+    /// We wait at most a few seconds for loads in progress.  After this
+    /// returns, the DirMgr doesn't start any new loads, but the
+    /// directory it has stays available.
+    ///
+    /// (There's no SQLite store in this tree yet, so there's no cache
+    /// to flush.)
+    pub async fn shutdown(self) {
+        let _ = self.shutdown_tx.send(true);
+        let wait = self.loads_in_progress.write();
+        if self.runtime.timeout(SHUTDOWN_TIMEOUT, wait).await.is_err() {
+            warn!(
+                "Directory load still running after {:?}; shutting down anyway.",
+                SHUTDOWN_TIMEOUT
+            );
+        }
+    }
+}
+
+/// Longest time that [`DirMgrHandle::shutdown`] waits for directory loads
+/// in progress to finish.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Number of events that can wait in a [`DirMgr::subscribe`] channel
+/// before the oldest ones are dropped.
+const EVENT_QUEUE_LEN: usize = 64;
+
 impl<R: Runtime> DirMgr<R> {
     /// Try to load the directory from disk, without launching any
     /// kind of update process.
@@ -90,7 +178,6 @@ impl<R: Runtime> DirMgr<R> {
     pub async fn load_once(runtime: R, config: NetDirConfig, docdir: &str) -> Result<Arc<NetDir>> {
         let dirmgr = Arc::new(Self::from_config(config, runtime));
 
-        // TODO: add some way to return a directory that isn't up-to-date
         let _success = dirmgr.load_directory(&docdir).await?;
 
         dirmgr
@@ -98,6 +185,29 @@ impl<R: Runtime> DirMgr<R> {
             .ok_or_else(|| Error::DirectoryNotPresent.into())
     }
 
+    /// Like [`DirMgr::load_once`], but accept a cached consensus even if
+    /// it has expired.
+    ///
+    /// This is for clients that are offline, and would rather use an
+    /// old directory than none at all.  Check the returned directory's
+    /// [`lifetime`](NetDir::lifetime) to see whether it is still timely.
+    /// The directory must still be complete enough to build circuits.
+    pub async fn load_once_stale(
+        runtime: R,
+        config: NetDirConfig,
+        docdir: &str,
+    ) -> Result<Arc<NetDir>> {
+        let dirmgr = Arc::new(Self::from_config(config, runtime));
+
+        let _success = dirmgr
+            .load_directory_with(docdir, CacheUsage::Stale)
+            .await?;
+
+        dirmgr
+            .opt_netdir()
+            .ok_or_else(|| Error::DirectoryNotPresent.into())
+    }
+
     /// Return a current netdir, either loading it or bootstrapping it
     /// as needed.
     ///
@@ -130,6 +240,10 @@ impl<R: Runtime> DirMgr<R> {
     ) -> Result<Arc<Self>> {
         let dirmgr = Arc::new(DirMgr::from_config(config, runtime));
 
+        // Note how the cache looks before we load it, so that we can
+        // tell later whether it has changed.
+        let cache_modified = cache_modified(docdir);
+
         // Try to load from the cache.
         dirmgr
             .load_directory(&docdir)
@@ -138,15 +252,100 @@ impl<R: Runtime> DirMgr<R> {
 
         info!("We have enough information to build circuits.");
 
+        // Launch a task to replace our consensus before it gets stale.
+        dirmgr.runtime.spawn(Self::prefetch_task(
+            Arc::downgrade(&dirmgr),
+            dirmgr.runtime.clone(),
+            docdir.to_string(),
+            cache_modified,
+        ))?;
+
         Ok(dirmgr)
     }
 
+    /// Return a receiver for a [`DirMgrEvent`] every time our directory
+    /// changes significantly.
+    ///
+    /// Receivers that fall more than a few dozen events behind will
+    /// miss the oldest ones; see [`broadcast::Receiver::recv`].
+    pub fn subscribe(&self) -> broadcast::Receiver<DirMgrEvent> {
+        self.event_tx.subscribe()
+    }
+
+    /// Background task to look for a new consensus once our current
+    /// one is close to no longer being fresh.
+    ///
+    /// We only reload the cache if its files have changed since
+    /// `last_modified`, the newest modification time they had when we
+    /// last loaded them.
+    ///
+    /// This task exits once the DirMgr is dropped or shut down.
+    async fn prefetch_task(
+        weak: Weak<Self>,
+        runtime: R,
+        docdir: String,
+        mut last_modified: Option<SystemTime>,
+    ) {
+        loop {
+            let (delay, shutdown) = match Weak::upgrade(&weak) {
+                Some(dirmgr) => (dirmgr.prefetch_delay(), dirmgr.shutdown_rx.clone()),
+                None => return,
+            };
+            if sleep_unless_shutdown(&runtime, delay, shutdown).await {
+                return;
+            }
+
+            let modified = cache_modified(&docdir);
+            if modified == last_modified {
+                debug!("Directory cache hasn't changed; not reloading it.");
+                continue;
+            }
+            last_modified = modified;
+
+            let dirmgr = match Weak::upgrade(&weak) {
+                Some(dirmgr) => dirmgr,
+                None => return,
+            };
+            info!("Consensus is close to expiry; looking for a new one.");
+            if let Err(e) = dirmgr.load_directory(&docdir).await {
+                warn!("Unable to replace consensus: {}", e);
+            }
+        }
+    }
+
+    /// Return how long to wait before we next try to replace our
+    /// consensus.
+    fn prefetch_delay(&self) -> Duration {
+        let lead = self.config.timing().prefetch_consensus_before_expiry();
+        match self.opt_netdir() {
+            Some(netdir) => prefetch_delay_for(netdir.lifetime(), lead, self.runtime.wallclock()),
+            None => MIN_PREFETCH_DELAY,
+        }
+    }
+
     /// Construct a DirMgr from a NetDirConfig.
     fn from_config(config: NetDirConfig, runtime: R) -> Self {
         let netdir = SharedMutArc::new();
+        let authority_stats = Mutex::new(
+            config
+                .authorities()
+                .iter()
+                .map(AuthorityDownloadStats::new)
+                .collect(),
+        );
+        let (expiry_tx, expiry_rx) = watch::channel(SystemTime::UNIX_EPOCH);
+        let (event_tx, _) = broadcast::channel(EVENT_QUEUE_LEN);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
         DirMgr {
             config,
             netdir,
+            authority_stats,
+            expiry_tx,
+            expiry_rx,
+            event_tx,
+            shutdown_tx: Arc::new(shutdown_tx),
+            shutdown_rx,
+            loads_in_progress: Arc::new(tokio_crate::sync::RwLock::new(())),
             runtime,
         }
     }
@@ -156,22 +355,94 @@ impl<R: Runtime> DirMgr<R> {
     ///
     /// Return false if there is no such consensus.
     async fn load_directory(self: &Arc<Self>, docdir: &str) -> Result<bool> {
-        let state = state::GetConsensusState::new(Arc::downgrade(self), CacheUsage::CacheOnly)
+        self.load_directory_with(docdir, CacheUsage::CacheOnly)
+            .await
+    }
+
+    /// Load the latest directory from the cache, using `cache_usage` to
+    /// decide which consensus to accept.
+    ///
+    /// Return false if there is no such consensus.  If we have no
+    /// directory, and the only cached consensus has expired, return
+    /// [`Error::ExpiredConsensus`] instead.
+    async fn load_directory_with(
+        self: &Arc<Self>,
+        docdir: &str,
+        cache_usage: CacheUsage,
+    ) -> Result<bool> {
+        let _guard = self.loads_in_progress.read().await;
+        if self.is_shutting_down() {
+            return Ok(self.netdir.get().is_some());
+        }
+        let state = state::GetConsensusState::new(Arc::downgrade(self), cache_usage)
             .context("Failed to create new GetConsensusState")?;
-        let _ = bootstrap::load(Box::new(state), &docdir).await?;
+        let state = bootstrap::load(Box::new(state), docdir).await?;
+
+        if self.netdir.get().is_some() {
+            return Ok(true);
+        }
+        match state.expired_consensus() {
+            Some(expired_at) => Err(Error::ExpiredConsensus { expired_at }.into()),
+            None => Ok(false),
+        }
+    }
 
-        Ok(self.netdir.get().is_some())
+    /// Stop this directory manager's background tasks, wait for any
+    /// directory load in progress to finish, and drop it.
+    ///
+    /// If this DirMgr is shared behind an `Arc`, use
+    /// [`DirMgr::shutdown_handle`] instead.
+    pub async fn shutdown(self) {
+        self.shutdown_handle().shutdown().await;
+    }
+
+    /// Return a handle that can shut down this directory manager.
+    ///
+    /// See [`DirMgrHandle::shutdown`].
+    pub fn shutdown_handle(&self) -> DirMgrHandle<R> {
+        DirMgrHandle {
+            shutdown_tx: Arc::clone(&self.shutdown_tx),
+            loads_in_progress: Arc::clone(&self.loads_in_progress),
+            runtime: self.runtime.clone(),
+        }
+    }
+
+    /// Return true if we have been told to shut down.
+    fn is_shutting_down(&self) -> bool {
+        *self.shutdown_rx.borrow()
     }
 
     /// Return an Arc handle to our latest directory, if we have one.
     ///
-    /// This is a private method, since by the time anybody else has a
-    /// handle to a DirMgr, the NetDir should definitely be
-    /// bootstrapped.
-    fn opt_netdir(&self) -> Option<Arc<NetDir>> {
+    /// Unlike [`DirMgr::netdir`], this doesn't panic if we haven't
+    /// bootstrapped yet.
+    pub fn opt_netdir(&self) -> Option<Arc<NetDir>> {
         self.netdir.get()
     }
 
+    /// Return a receiver that holds the `valid-until` time of our current
+    /// consensus.
+    ///
+    /// The value is updated every time we accept a new consensus, so
+    /// callers can wait on the receiver to learn when the directory
+    /// they hold will expire, and re-bootstrap before then.  Until we
+    /// have a consensus, the value is the Unix epoch.
+    pub fn consensus_expiry_watch(&self) -> watch::Receiver<SystemTime> {
+        self.expiry_rx.clone()
+    }
+
+    /// Return the configuration that this directory manager was built
+    /// with.
+    pub fn config(&self) -> &NetDirConfig {
+        &self.config
+    }
+
+    /// Return statistics about our attempts to fetch certificates from
+    /// each configured authority.
+    pub fn authority_stats(&self) -> Vec<AuthorityDownloadStats> {
+        self.authority_stats.lock().unwrap().clone()
+    }
+
     /// Return an Arc handle to our latest directory, if we have one.
     // TODO: Add variants of this that make sure that it's up-to-date?
     pub fn netdir(&self) -> Arc<NetDir> {
@@ -195,6 +466,8 @@ impl<R: Runtime> DirMgr<R> {
 /// because the information it has downloaded is no longer timely.
 #[async_trait]
 trait DirState: Send {
+    /// Return the bootstrap phase that this state represents.
+    fn bootstrap_phase(&self) -> BootstrapPhase;
     /// Return a human-readable description of this state.
     fn describe(&self) -> String;
     /// Return a list of the documents we're missing.
@@ -220,4 +493,131 @@ trait DirState: Send {
     fn reset_time(&self) -> Option<SystemTime>;
     /// Reset this state and start over.
     fn reset(self: Box<Self>) -> Result<Box<dyn DirState>>;
+    /// If this state rejected a cached consensus because it had expired,
+    /// return the time at which that consensus expired.
+    fn expired_consensus(&self) -> Option<SystemTime> {
+        None
+    }
+}
+
+/// Sleep for `duration`, or until `shutdown` tells us that our DirMgr is
+/// shutting down.
+///
+/// Return true if it is shutting down.
+async fn sleep_unless_shutdown<R: Runtime>(
+    runtime: &R,
+    duration: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) -> bool {
+    if *shutdown.borrow() {
+        return true;
+    }
+    {
+        let sleep = runtime.sleep(duration);
+        // This also finishes if the DirMgr has been dropped.
+        let changed = shutdown.changed();
+        futures::pin_mut!(sleep, changed);
+        futures::future::select(sleep, changed).await;
+    }
+    let shutting_down = *shutdown.borrow();
+    shutting_down
+}
+
+/// Shortest time we'll wait between attempts to replace our consensus,
+/// so that we don't spin once it is already due for replacement.
+const MIN_PREFETCH_DELAY: Duration = Duration::from_secs(60);
+
+/// Return how long to wait, starting at `now`, before we look for a
+/// replacement for a consensus with the lifetime `lifetime`, trying to
+/// start `lead` before it stops being fresh.
+///
+/// We never start earlier than halfway through the consensus's fresh
+/// interval: consensuses are usually only fresh for an hour, which is
+/// less than the default `lead`.
+fn prefetch_delay_for(
+    lifetime: &tor_netdoc::doc::netstatus::Lifetime,
+    lead: Duration,
+    now: SystemTime,
+) -> Duration {
+    let fresh_for = lifetime
+        .fresh_until()
+        .duration_since(lifetime.valid_after())
+        .unwrap_or_default();
+    let lead = lead.min(fresh_for / 2);
+    let target = lifetime.fresh_until() - lead;
+    target
+        .duration_since(now)
+        .unwrap_or(MIN_PREFETCH_DELAY)
+        .max(MIN_PREFETCH_DELAY)
+}
+
+/// Names of the side-loaded files that we read from the cache
+/// directory.
+const CACHE_FILES: &[&str] = &[
+    "consensus.txt",
+    "churn.txt",
+    "certificate.txt",
+    "microdescriptors.txt",
+];
+
+/// Return the newest modification time of the files in the cache
+/// directory `docdir`, or None if none of them can be read.
+fn cache_modified(docdir: &str) -> Option<SystemTime> {
+    CACHE_FILES
+        .iter()
+        .filter_map(|name| std::fs::metadata(Path::new(docdir).join(name)).ok())
+        .filter_map(|meta| meta.modified().ok())
+        .max()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tor_netdoc::doc::netstatus::Lifetime;
+
+    #[test]
+    fn prefetch_delay() {
+        let hour = Duration::from_secs(60 * 60);
+        let valid_after = SystemTime::UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        // A typical consensus: fresh for an hour, valid for three.
+        let lifetime =
+            Lifetime::new(valid_after, valid_after + hour, valid_after + 3 * hour).unwrap();
+        let minutes = |n: u64| Duration::from_secs(60 * n);
+
+        // The default lead is longer than the fresh interval, so we
+        // start halfway through it.
+        let lead = 3 * hour;
+        assert_eq!(
+            prefetch_delay_for(&lifetime, lead, valid_after + minutes(5)),
+            minutes(25)
+        );
+        // A shorter lead is used as-is.
+        assert_eq!(
+            prefetch_delay_for(&lifetime, minutes(10), valid_after + minutes(5)),
+            minutes(45)
+        );
+        // Once we're past the time to start, we wait the minimum delay.
+        for late in &[minutes(30), minutes(59), 2 * hour, 5 * hour] {
+            assert_eq!(
+                prefetch_delay_for(&lifetime, lead, valid_after + *late),
+                MIN_PREFETCH_DELAY
+            );
+        }
+    }
+
+    #[test]
+    fn cache_modified_times() {
+        let tmp = tempdir::TempDir::new("arti-dirmgr").unwrap();
+        let dir = tmp.path();
+        let docdir = dir.to_str().unwrap();
+        assert_eq!(cache_modified(docdir), None);
+
+        std::fs::write(dir.join("consensus.txt"), "x").unwrap();
+        std::fs::write(dir.join("unrelated.txt"), "x").unwrap();
+        let consensus_time = std::fs::metadata(dir.join("consensus.txt"))
+            .unwrap()
+            .modified()
+            .unwrap();
+        assert_eq!(cache_modified(docdir), Some(consensus_time));
+    }
 }
@@ -33,6 +33,12 @@ pub struct RetryDelay {
     last_delay_ms: u32,
     /// The lowest allowable delay (in msec).
     low_bound_ms: u32,
+    /// The highest allowable delay (in msec).  This is only a soft
+    /// limit: it never pushes a delay below `low_bound_ms`.
+    high_bound_ms: u32,
+    /// The sum of all the delays that this retry delay has returned
+    /// since it was created or reset (in msec).
+    total_elapsed_ms: u64,
 }
 
 /// Lowest possible lower bound, in milliseconds.
@@ -64,6 +70,21 @@ impl RetryDelay {
         RetryDelay {
             last_delay_ms: 0,
             low_bound_ms,
+            high_bound_ms: u32::MAX,
+            total_elapsed_ms: 0,
+        }
+    }
+
+    /// Return a copy of this RetryDelay that never yields a delay
+    /// longer than `max`.
+    ///
+    /// If `max` is less than the base delay, every delay will be the
+    /// base delay.
+    pub fn with_max_delay(self, max: Duration) -> Self {
+        let high_bound_ms = std::cmp::min(max.as_millis(), u32::MAX as u128) as u32;
+        RetryDelay {
+            high_bound_ms,
+            ..self
         }
     }
 
@@ -84,7 +105,10 @@ impl RetryDelay {
             // We don't need a saturating_add here, since low is always
             // less than high, so low cannot be equal to u32::MAX.
             low + 1,
-            self.last_delay_ms.saturating_mul(MAX_DELAY_MULT),
+            std::cmp::min(
+                self.last_delay_ms.saturating_mul(MAX_DELAY_MULT),
+                self.high_bound_ms,
+            ),
         );
         (low, high)
     }
@@ -97,6 +121,7 @@ impl RetryDelay {
 
         let val = rng.gen_range(low..high);
         self.last_delay_ms = val;
+        self.total_elapsed_ms = self.total_elapsed_ms.saturating_add(val as u64);
         val
     }
 
@@ -105,6 +130,25 @@ impl RetryDelay {
     pub fn next_delay<R: Rng>(&mut self, rng: &mut R) -> Duration {
         Duration::from_millis(self.next_delay_msec(rng) as u64)
     }
+
+    /// Return this retry delay to the state it was in when it was
+    /// constructed, so that the next delay is short again.
+    ///
+    /// Call this after a download succeeds.  The lower bound is
+    /// unchanged, and the elapsed total starts again from zero.
+    pub fn reset(&mut self) {
+        self.last_delay_ms = 0;
+        self.total_elapsed_ms = 0;
+    }
+
+    /// Return the sum of all the delays (in milliseconds) that this
+    /// retry delay has returned since it was created or reset.
+    ///
+    /// Callers can use this to give up once a whole sequence of
+    /// retries has taken too long.
+    pub fn total_elapsed_msec(&self) -> u64 {
+        self.total_elapsed_ms
+    }
 }
 
 impl Default for RetryDelay {
@@ -125,6 +169,11 @@ pub struct RetryConfig {
     /// lower-bound for future delays.
     #[serde(with = "humantime_serde")]
     initial_delay: Duration,
+
+    /// The longest that we should ever delay between two attempts, if
+    /// there is a limit.
+    #[serde(default, with = "humantime_serde::option")]
+    max_delay: Option<Duration>,
 }
 
 impl Default for RetryConfig {
@@ -132,6 +181,7 @@ impl Default for RetryConfig {
         RetryConfig {
             num: 3.try_into().expect("3 is not 0"),
             initial_delay: Duration::from_millis(1000),
+            max_delay: None,
         }
     }
 }
@@ -147,7 +197,50 @@ impl RetryConfig {
         let num = attempts
             .try_into()
             .unwrap_or_else(|_| 1.try_into().unwrap());
-        RetryConfig { num, initial_delay }
+        RetryConfig {
+            num,
+            initial_delay,
+            max_delay: None,
+        }
+    }
+
+    /// Return a copy of this RetryConfig that never waits longer than
+    /// `max_delay` between two attempts.
+    pub fn with_max_delay(self, max_delay: Duration) -> Self {
+        RetryConfig {
+            max_delay: Some(max_delay),
+            ..self
+        }
+    }
+
+    /// Return the longest that we should wait between two attempts, if
+    /// there is a limit.
+    pub fn max_delay(&self) -> Option<Duration> {
+        self.max_delay
+    }
+
+    /// Create a new RetryConfig from the environment variables
+    /// `{env_prefix}_RETRY_ATTEMPTS` and `{env_prefix}_RETRY_DELAY_MS`.
+    ///
+    /// Any variable that is unset or unparseable is replaced with its
+    /// default value.  This is mainly useful for tests, where the
+    /// default delay would make retries slow.
+    pub fn from_env(env_prefix: &str) -> Self {
+        /// Return the value of environment variable `name`, if it is set
+        /// and parses as a `T`.
+        fn var<T: std::str::FromStr>(name: String) -> Option<T> {
+            std::env::var(name).ok()?.parse().ok()
+        }
+        let default = RetryConfig::default();
+        let num = var(format!("{}_RETRY_ATTEMPTS", env_prefix)).unwrap_or(default.num);
+        let initial_delay = var(format!("{}_RETRY_DELAY_MS", env_prefix))
+            .map(Duration::from_millis)
+            .unwrap_or(default.initial_delay);
+        RetryConfig {
+            num,
+            initial_delay,
+            max_delay: None,
+        }
     }
 
     /// Return an iterator to use over all the supported attempts for
@@ -166,7 +259,11 @@ impl RetryConfig {
     ///
     /// If the initial delay is longer than 32
     pub fn schedule(&self) -> RetryDelay {
-        RetryDelay::from_duration(self.initial_delay)
+        let delay = RetryDelay::from_duration(self.initial_delay);
+        match self.max_delay {
+            Some(max) => delay.with_max_delay(max),
+            None => delay,
+        }
     }
 }
 
@@ -197,6 +294,50 @@ mod test {
         assert_eq!(rd.delay_bounds(), (1000, 4500));
         rd.last_delay_ms = 3_000_000_000;
         assert_eq!(rd.delay_bounds(), (1000, std::u32::MAX));
+
+        let mut rd = RetryDelay::from_msec(1000).with_max_delay(Duration::from_secs(2));
+        rd.last_delay_ms = 1500;
+        assert_eq!(rd.delay_bounds(), (1000, 2000));
+        rd.last_delay_ms = 3_000_000_000;
+        assert_eq!(rd.delay_bounds(), (1000, 2000));
+        // The lower bound wins over a smaller maximum.
+        let rd = RetryDelay::from_msec(5000).with_max_delay(Duration::from_secs(2));
+        assert_eq!(rd.delay_bounds(), (5000, 5001));
+    }
+
+    #[test]
+    fn max_delay() {
+        let rc = RetryConfig::new(10, Duration::from_secs(1));
+        assert_eq!(rc.max_delay(), None);
+        let rc = rc.with_max_delay(Duration::from_secs(3));
+        assert_eq!(rc.max_delay(), Some(Duration::from_secs(3)));
+
+        let mut rd = rc.schedule();
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            assert!(rd.next_delay(&mut rng) < Duration::from_secs(3));
+        }
+    }
+
+    #[test]
+    fn from_env() {
+        let prefix = "ARTI_RETRY_FROM_ENV_TEST";
+        let rc = RetryConfig::from_env(prefix);
+        assert_eq!(rc.n_attempts(), 3);
+        assert_eq!(rc.initial_delay, Duration::from_millis(1000));
+
+        std::env::set_var(format!("{}_RETRY_ATTEMPTS", prefix), "7");
+        std::env::set_var(format!("{}_RETRY_DELAY_MS", prefix), "10");
+        let rc = RetryConfig::from_env(prefix);
+        assert_eq!(rc.n_attempts(), 7);
+        assert_eq!(rc.initial_delay, Duration::from_millis(10));
+
+        // Zero attempts isn't allowed, so we use the default.
+        std::env::set_var(format!("{}_RETRY_ATTEMPTS", prefix), "0");
+        std::env::set_var(format!("{}_RETRY_DELAY_MS", prefix), "soon");
+        let rc = RetryConfig::from_env(prefix);
+        assert_eq!(rc.n_attempts(), 3);
+        assert_eq!(rc.initial_delay, Duration::from_millis(1000));
     }
 
     #[test]
@@ -215,4 +356,22 @@ mod test {
             assert!(delay < b_hi);
         }
     }
+
+    #[test]
+    fn reset_and_total() {
+        let mut rd = RetryDelay::from_msec(2000);
+        let mut rng = rand::thread_rng();
+        let mut total = 0;
+        for _ in 0..5 {
+            total += rd.next_delay_msec(&mut rng) as u64;
+        }
+        assert_eq!(rd.total_elapsed_msec(), total);
+        assert!(rd.last_delay_ms >= 2000);
+
+        rd.reset();
+        assert_eq!(rd.last_delay_ms, 0);
+        assert_eq!(rd.low_bound_ms, 2000);
+        assert_eq!(rd.total_elapsed_msec(), 0);
+        assert_eq!(rd.delay_bounds(), (2000, 2001));
+    }
 }
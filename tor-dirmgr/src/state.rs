@@ -30,10 +30,10 @@ use tor_netdir::{MdReceiver, NetDir, PartialNetDir};
 use tor_netdoc::doc::netstatus::Lifetime;
 
 use crate::{
-    docmeta::ConsensusMeta, shared_ref::SharedMutArc, CacheUsage, DirState, DocId, Error,
-    NetDirConfig, Result,
+    docmeta::ConsensusMeta, shared_ref::SharedMutArc, BootstrapPhase, CacheUsage, DirMgrEvent,
+    DirState, DocId, Error, NetDirConfig, Result,
 };
-use tor_checkable::{ExternallySigned, SelfSigned, Timebound};
+use tor_checkable::{ExternallySigned, SelfSigned, TimeValidityError, Timebound};
 use tor_llcrypto::pk::rsa::RsaIdentity;
 use tor_netdoc::doc::{
     microdesc::{MdDigest, Microdesc},
@@ -54,7 +54,8 @@ use tor_rtcompat::Runtime;
 /// Note that there's only one implementation for this trait: DirMgr.
 /// We make this a trait anyway to make sure that the different states
 /// in this module can _only_ interact with the DirMgr through
-/// modifying the NetDir and looking at the configuration.
+/// modifying the NetDir, looking at the configuration, and reporting
+/// statistics.
 pub(crate) trait WriteNetDir: 'static + Sync + Send {
     /// Return a NetDirConfig to use when asked how to retry downloads,
     /// or when we need to find a list of descriptors.
@@ -62,6 +63,17 @@ pub(crate) trait WriteNetDir: 'static + Sync + Send {
 
     /// Return a reference where we can write or modify a NetDir.
     fn netdir(&self) -> &SharedMutArc<NetDir>;
+
+    /// Record the outcome of an attempt to fetch a certificate for the
+    /// authority whose identity is `authority`.
+    fn note_cert_fetch(&self, authority: &RsaIdentity, outcome: std::result::Result<(), String>);
+
+    /// Tell any watchers that we have accepted a new consensus that is
+    /// valid until `valid_until`.
+    fn note_consensus_expiry(&self, valid_until: SystemTime);
+
+    /// Tell any subscribers about `event`.
+    fn send_event(&self, event: DirMgrEvent);
 }
 
 impl<R: Runtime> WriteNetDir for crate::DirMgr<R> {
@@ -71,6 +83,19 @@ impl<R: Runtime> WriteNetDir for crate::DirMgr<R> {
     fn netdir(&self) -> &SharedMutArc<NetDir> {
         &self.netdir
     }
+    fn note_cert_fetch(&self, authority: &RsaIdentity, outcome: std::result::Result<(), String>) {
+        let mut stats = self.authority_stats.lock().unwrap();
+        if let Some(s) = stats.iter_mut().find(|s| s.v3ident() == authority) {
+            s.note_attempt(outcome);
+        }
+    }
+    fn note_consensus_expiry(&self, valid_until: SystemTime) {
+        let _ = self.expiry_tx.send(valid_until);
+    }
+    fn send_event(&self, event: DirMgrEvent) {
+        // This only fails if there are no subscribers.
+        let _ = self.event_tx.send(event);
+    }
 }
 
 /// Initial state: fetching or loading a consensus directory.
@@ -90,6 +115,14 @@ pub(crate) struct GetConsensusState<DM: WriteNetDir> {
     /// more than half of these authorities.
     authority_ids: Vec<RsaIdentity>,
 
+    /// How many of `authority_ids` must sign a consensus.  This is more
+    /// than half of them unless the configuration says otherwise.
+    n_signatures_needed: usize,
+
+    /// If we rejected a cached consensus because it had expired, the
+    /// time when it expired.
+    expired_at: Option<SystemTime>,
+
     /// A weak reference to the directory manager that wants us to
     /// fetch this information.  When this references goes away, we exit.
     writedir: Weak<DM>,
@@ -99,18 +132,21 @@ impl<DM: WriteNetDir> GetConsensusState<DM> {
     /// Create a new GetConsensusState from a weak reference to a
     /// directory manager and a `cache_usage` flag.
     pub(crate) fn new(writedir: Weak<DM>, cache_usage: CacheUsage) -> Result<Self> {
-        let authority_ids: Vec<_> = Weak::upgrade(&writedir)
-            .context(Error::ManagerDropped)?
-            .config()
+        let dm = Weak::upgrade(&writedir).context(Error::ManagerDropped)?;
+        let config = dm.config();
+        let authority_ids: Vec<_> = config
             .authorities()
             .iter()
             .map(|auth| *auth.v3ident())
             .collect();
+        let n_signatures_needed = config.n_signatures_needed();
 
         Ok(GetConsensusState {
             cache_usage,
             next: None,
             authority_ids,
+            n_signatures_needed,
+            expired_at: None,
             writedir,
         })
     }
@@ -118,6 +154,9 @@ impl<DM: WriteNetDir> GetConsensusState<DM> {
 
 #[async_trait]
 impl<DM: WriteNetDir> DirState for GetConsensusState<DM> {
+    fn bootstrap_phase(&self) -> BootstrapPhase {
+        BootstrapPhase::FetchingConsensus
+    }
     fn describe(&self) -> String {
         if self.next.is_some() {
             "About to fetch certificates."
@@ -126,6 +165,7 @@ impl<DM: WriteNetDir> DirState for GetConsensusState<DM> {
                 CacheUsage::CacheOnly => "Looking for a cached consensus.",
                 CacheUsage::CacheOkay => "Looking for a consensus.",
                 CacheUsage::MustDownload => "Downloading a consensus.",
+                CacheUsage::Stale => "Looking for a cached consensus, even an expired one.",
             }
         }
         .to_string()
@@ -155,7 +195,7 @@ impl<DM: WriteNetDir> DirState for GetConsensusState<DM> {
             .map_or(Ok(Vec::new()), parse_churn)
             .context("Failed to parse churn info.")?;
 
-        self.add_consensus_text(true, consensus.as_str(), churn)
+        self.add_consensus_text(consensus.as_str(), churn)
             .map(|meta| meta.is_some())
     }
     fn advance(self: Box<Self>) -> Result<Box<dyn DirState>> {
@@ -170,6 +210,9 @@ impl<DM: WriteNetDir> DirState for GetConsensusState<DM> {
     fn reset(self: Box<Self>) -> Result<Box<dyn DirState>> {
         Ok(self)
     }
+    fn expired_consensus(&self) -> Option<SystemTime> {
+        self.expired_at
+    }
 }
 
 /// Parse churned routers info.
@@ -198,19 +241,30 @@ impl<DM: WriteNetDir> GetConsensusState<DM> {
     /// correct, or if it is illformed.
     fn add_consensus_text(
         &mut self,
-        from_cache: bool,
         text: &str,
         churn: Vec<RsaIdentity>,
     ) -> Result<Option<&ConsensusMeta>> {
         // Try to parse it and get its metadata.
         let (consensus_meta, mut unvalidated) = {
             let (signed_part, remainder, parsed) = MdConsensus::parse(text)?;
-            if let Ok(timely) = parsed.check_valid_now() {
-                let meta = ConsensusMeta::from_unvalidated(signed_part, remainder, &timely);
-                (meta, timely)
-            } else {
-                return Ok(None);
-            }
+            let now = SystemTime::now();
+            let timely = match parsed.is_valid_at(&now) {
+                Ok(()) => parsed.dangerously_assume_timely(),
+                // Even in stale mode, a consensus from the future is
+                // never acceptable.
+                Err(e @ TimeValidityError::Expired(_)) if self.cache_usage == CacheUsage::Stale => {
+                    warn!("Using an expired consensus: {}", e);
+                    parsed.dangerously_assume_timely()
+                }
+                Err(e) => {
+                    if let TimeValidityError::Expired(age) = e {
+                        self.expired_at = now.checked_sub(age);
+                    }
+                    return Ok(None);
+                }
+            };
+            let meta = ConsensusMeta::from_unvalidated(signed_part, remainder, &timely);
+            (meta, timely)
         };
 
         // If the churn is above a threshold, we only consider a random subset
@@ -236,11 +290,18 @@ impl<DM: WriteNetDir> GetConsensusState<DM> {
         // Check out what authorities we believe in, and see if enough
         // of them are purported to have singed this consensus.
         let n_authorities = self.authority_ids.len() as u16;
-        let unvalidated = unvalidated.set_n_authorities(n_authorities);
+        let unvalidated = unvalidated
+            .set_n_authorities(n_authorities)
+            .set_threshold(self.n_signatures_needed as u16);
 
         let id_refs: Vec<_> = self.authority_ids.iter().collect();
         if !unvalidated.authorities_are_correct(&id_refs[..]) {
-            return Err(Error::UnrecognizedAuthorities.into());
+            let got = unvalidated.n_signers_among(&id_refs[..]);
+            if got == 0 {
+                return Err(Error::UnrecognizedAuthorities.into());
+            }
+            let needed = self.n_signatures_needed;
+            return Err(Error::AuthorityConsensusThreshold { got, needed }.into());
         }
 
         // Make a set of all the certificates we want -- the subset of
@@ -253,7 +314,6 @@ impl<DM: WriteNetDir> GetConsensusState<DM> {
 
         self.next = Some(GetCertsState {
             cache_usage: self.cache_usage,
-            from_cache,
             unvalidated,
             consensus_meta,
             missing_certs: desired_certs,
@@ -282,8 +342,6 @@ impl<DM: WriteNetDir> GetConsensusState<DM> {
 struct GetCertsState<DM: WriteNetDir> {
     /// The cache usage we had in mind when we began.  Used to reset.
     cache_usage: CacheUsage,
-    /// True iff we loaded the consensus from our cache.
-    from_cache: bool,
     /// The consensus that we are trying to validate.
     unvalidated: UnvalidatedMdConsensus,
     /// Metadata for the consensus.
@@ -299,6 +357,9 @@ struct GetCertsState<DM: WriteNetDir> {
 
 #[async_trait]
 impl<DM: WriteNetDir> DirState for GetCertsState<DM> {
+    fn bootstrap_phase(&self) -> BootstrapPhase {
+        BootstrapPhase::FetchingCerts
+    }
     fn describe(&self) -> String {
         let total = self.certs.len() + self.missing_certs.len();
         format!(
@@ -317,19 +378,24 @@ impl<DM: WriteNetDir> DirState for GetCertsState<DM> {
         self.unvalidated.key_is_correct(&self.certs[..]).is_ok()
     }
     fn add_from_cache(&mut self, docdir: &str) -> Result<bool> {
-        let mut changed = false;
-        // side-loaded data
-        let certificate_path = format!("{}/certificate.txt", docdir);
-        let certificate =
-            fs::read_to_string(certificate_path).context("Failed to read the certificate.")?;
-
-        let parsed = AuthCert::parse(certificate.as_str())?.check_signature()?;
-        if let Ok(cert) = parsed.check_valid_now() {
-            self.missing_certs.remove(cert.key_ids());
-            self.certs.push(cert);
-            changed = true;
+        let wanted: Vec<RsaIdentity> = self
+            .missing_certs
+            .iter()
+            .map(|ids| ids.id_fingerprint)
+            .collect();
+        let result = self.add_cert_from_file(docdir);
+        if let Some(wd) = Weak::upgrade(&self.writedir) {
+            match &result {
+                Ok(Some(id)) => wd.note_cert_fetch(id, Ok(())),
+                Ok(None) => {}
+                Err(e) => {
+                    for id in &wanted {
+                        wd.note_cert_fetch(id, Err(e.to_string()));
+                    }
+                }
+            }
         }
-        Ok(changed)
+        result.map(|id| id.is_some())
     }
     fn advance(self: Box<Self>) -> Result<Box<dyn DirState>> {
         if self.can_advance() {
@@ -337,6 +403,12 @@ impl<DM: WriteNetDir> DirState for GetCertsState<DM> {
                 .unvalidated
                 .check_signature(&self.certs[..])
                 .context("Consensus validation failed.")?;
+            if let Some(wd) = Weak::upgrade(&self.writedir) {
+                info!(
+                    "Using a consensus from {}.",
+                    wd.config().consensus_source(&validated)
+                );
+            }
 
             Ok(Box::new(GetMicrodescsState::new(
                 validated,
@@ -358,11 +430,42 @@ impl<DM: WriteNetDir> DirState for GetCertsState<DM> {
     }
 }
 
+impl<DM: WriteNetDir> GetCertsState<DM> {
+    /// Helper: try to load a certificate from the side-loaded file in
+    /// `docdir`.
+    ///
+    /// On success, return the identity of the authority whose certificate
+    /// we added, or None if the certificate wasn't currently valid.
+    fn add_cert_from_file(&mut self, docdir: &str) -> Result<Option<RsaIdentity>> {
+        // side-loaded data
+        let certificate_path = format!("{}/certificate.txt", docdir);
+        let certificate =
+            fs::read_to_string(certificate_path).context("Failed to read the certificate.")?;
+
+        let parsed = AuthCert::parse(certificate.as_str())
+            .map_err(|e| match e {
+                tor_netdoc::Error::BadDocumentVersion(v) => Error::UnsupportedCertVersion(v).into(),
+                e => anyhow::Error::from(e),
+            })?
+            .check_signature()?;
+        if let Ok(cert) = parsed.check_valid_now() {
+            let id = *cert.id_fingerprint();
+            self.missing_certs.remove(cert.key_ids());
+            self.certs.push(cert);
+            Ok(Some(id))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
 /// Final state: we're fetching or loading microdescriptors
 #[derive(Debug, Clone)]
 struct GetMicrodescsState<DM: WriteNetDir> {
     /// The digests of the microdesscriptors we are missing.
     missing: HashSet<MdDigest>,
+    /// The total number of microdescriptors listed in the consensus.
+    n_microdescs: usize,
     /// The dirmgr to inform about a usable directory.
     writedir: Weak<DM>,
     /// A NetDir that we are currently building, but which doesn't
@@ -384,9 +487,13 @@ impl<DM: WriteNetDir> GetMicrodescsState<DM> {
     /// microdescriptor consensus.
     fn new(consensus: MdConsensus, meta: ConsensusMeta, writedir: Weak<DM>) -> Result<Self> {
         let reset_time = consensus.lifetime().valid_until();
+        let n_microdescs = consensus.routers().len();
 
         let partial_dir = match Weak::upgrade(&writedir) {
             Some(wd) => {
+                wd.send_event(DirMgrEvent::NewConsensus {
+                    valid_after: consensus.lifetime().valid_after(),
+                });
                 let params = wd.config().override_net_params();
                 let mut dir = PartialNetDir::new(consensus, Some(params));
                 if let Some(old_dir) = wd.netdir().get() {
@@ -400,6 +507,7 @@ impl<DM: WriteNetDir> GetMicrodescsState<DM> {
         let missing = partial_dir.missing_microdescs().map(Clone::clone).collect();
         let mut result = GetMicrodescsState {
             missing,
+            n_microdescs,
             writedir,
             partial: Some(partial_dir),
             meta,
@@ -423,29 +531,53 @@ impl<DM: WriteNetDir> GetMicrodescsState<DM> {
                 self.newly_listed.push(*md.digest());
                 p.add_microdesc(md);
             }
-            return self.consider_upgrade();
+            let upgraded = self.consider_upgrade()?;
+            if !upgraded {
+                self.note_progress();
+            }
+            return Ok(upgraded);
         } else if let Some(wd) = Weak::upgrade(&self.writedir) {
+            let mut added = false;
             let _ = wd.netdir().mutate(|nd| {
                 for md in mds {
-                    nd.add_microdesc(md);
+                    added |= nd.add_microdesc(md);
                 }
                 Ok(())
             });
+            if added {
+                wd.send_event(DirMgrEvent::NewNetDir);
+            }
         }
         Ok(false)
     }
 
+    /// Tell the dirmgr how much of the network we have microdescriptors
+    /// for so far.
+    fn note_progress(&self) {
+        if let (Some(p), Some(wd)) = (&self.partial, Weak::upgrade(&self.writedir)) {
+            let fraction = p.fraction_available() as f32;
+            wd.send_event(DirMgrEvent::BootstrapProgress { fraction });
+        }
+    }
+
     /// Check whether this netdir we're building has _just_ become
     /// usable when it was not previously usable.  If so, tell the
     /// dirmgr about it and return true; otherwise return false.
     fn consider_upgrade(&mut self) -> Result<bool> {
-        if let Some(p) = self.partial.take() {
+        if let Some(mut p) = self.partial.take() {
+            let n_excess = p.remove_excess_microdescs();
+            if n_excess > 0 {
+                debug!("Discarded {} unreferenced microdescriptors.", n_excess);
+            }
             match p.unwrap_if_sufficient() {
                 Ok(netdir) => {
                     self.reset_time =
                         pick_download_time(netdir.lifetime()).context("picking download time")?;
                     if let Some(wd) = Weak::upgrade(&self.writedir) {
+                        let valid_until = netdir.lifetime().valid_until();
                         wd.netdir().replace(netdir);
+                        wd.note_consensus_expiry(valid_until);
+                        wd.send_event(DirMgrEvent::NewNetDir);
                         return Ok(true);
                     }
                 }
@@ -458,6 +590,16 @@ impl<DM: WriteNetDir> GetMicrodescsState<DM> {
 
 #[async_trait]
 impl<DM: WriteNetDir> DirState for GetMicrodescsState<DM> {
+    fn bootstrap_phase(&self) -> BootstrapPhase {
+        if self.missing.is_empty() {
+            BootstrapPhase::Complete
+        } else {
+            BootstrapPhase::FetchingMicrodescs {
+                fetched: self.n_microdescs.saturating_sub(self.missing.len()),
+                total: self.n_microdescs,
+            }
+        }
+    }
     fn describe(&self) -> String {
         format!(
             "Downloading microdescriptors (we are missing {}).",
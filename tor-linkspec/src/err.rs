@@ -0,0 +1,13 @@
+//! Declare an error type for the tor-linkspec crate.
+
+use thiserror::Error;
+
+/// An error related to a relay's identity.
+#[derive(Debug, Clone, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// The target has no Ed25519 identity, so it can only be
+    /// authenticated by its RSA identity.
+    #[error("target has no Ed25519 identity")]
+    NoEd25519Identity,
+}
@@ -15,8 +15,12 @@
 #![deny(clippy::missing_docs_in_private_items)]
 #![warn(clippy::rc_buffer)]
 
+mod err;
 mod ls;
+mod owned;
 mod traits;
 
-pub use ls::LinkSpec;
+pub use err::Error;
+pub use ls::{link_specs_are_canonical, sort_link_specs, LinkSpec};
+pub use owned::{OwnedChanTarget, OwnedCircTarget};
 pub use traits::{ChanTarget, CircTarget};
@@ -11,7 +11,7 @@ use tor_llcrypto::pk::rsa::RsaIdentity;
 
 /// A piece of information about a relay and how to connect to it.
 #[non_exhaustive]
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LinkSpec {
     /// The TCP address of an OR Port for a relay
     OrPort(IpAddr, u16),
@@ -147,6 +147,57 @@ impl LinkSpec {
     pub fn sort_by_type(lst: &mut [Self]) {
         lst.sort_by_key(LinkSpec::sort_pos)
     }
+
+    /// Helper: return the type byte of this linkspec, and its body as
+    /// it would be encoded.
+    fn type_and_body(&self) -> (u8, Vec<u8>) {
+        let mut v = Vec::new();
+        v.write(self);
+        let body = v.split_off(2);
+        (v[0], body)
+    }
+}
+
+/// Link specifiers are ordered by the position in which they should
+/// occur in an EXTEND2 cell, then by type byte, then by their encoded
+/// bodies.
+///
+/// Note that the position order is not the same as the type order:
+/// IPv6 addresses (type 1) go after both identities.
+impl Ord for LinkSpec {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let (my_type, my_body) = self.type_and_body();
+        let (other_type, other_body) = other.type_and_body();
+        self.sort_pos()
+            .cmp(&other.sort_pos())
+            .then(my_type.cmp(&other_type))
+            .then_with(|| my_body.cmp(&other_body))
+            // An Unrecognized linkspec might have the same encoding as a
+            // recognized one; keep the ordering consistent with Eq.
+            .then_with(|| {
+                let unrecognized = |ls: &LinkSpec| matches!(ls, LinkSpec::Unrecognized(_, _));
+                unrecognized(self).cmp(&unrecognized(other))
+            })
+    }
+}
+impl PartialOrd for LinkSpec {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Sort `specs` into the canonical order for an EXTEND2 cell.
+///
+/// This is a total order: unlike [`LinkSpec::sort_by_type`], the result
+/// doesn't depend on the order of the input.
+pub fn sort_link_specs(specs: &mut [LinkSpec]) {
+    specs.sort();
+}
+
+/// Return true if `specs` is in the canonical order for an EXTEND2 cell,
+/// as produced by [`sort_link_specs`].
+pub fn link_specs_are_canonical(specs: &[LinkSpec]) -> bool {
+    specs.windows(2).all(|w| w[0] <= w[1])
 }
 
 #[cfg(test)]
@@ -213,4 +264,33 @@ mod test {
         assert!(matches!(t(&hex!("00 06 01020304")), Error::Truncated));
         assert!(matches!(t(&hex!("99 07 010203")), Error::Truncated));
     }
+
+    #[test]
+    fn test_canonical_order() {
+        let v4a = LinkSpec::OrPort(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 80);
+        let v4b = LinkSpec::OrPort(IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), 443);
+        let v6 = LinkSpec::OrPort(IpAddr::V6(Ipv6Addr::new(1, 2, 3, 4, 5, 6, 7, 8)), 443);
+        let rsa = LinkSpec::RsaId(RsaIdentity::from_bytes(b"hello world!!!!!!!!!").unwrap());
+        let ed = LinkSpec::Ed25519Id([7; 32].into());
+        let odd = LinkSpec::Unrecognized(77, b"strange".to_vec());
+        // Looks like v4a on the wire, but isn't equal to it.
+        let fake_v4 = LinkSpec::Unrecognized(0, hex!("01020304 0050").to_vec());
+
+        let canonical = vec![
+            v4a.clone(),
+            fake_v4.clone(),
+            v4b.clone(),
+            rsa.clone(),
+            ed.clone(),
+            v6.clone(),
+            odd.clone(),
+        ];
+        assert!(link_specs_are_canonical(&canonical));
+        assert!(link_specs_are_canonical(&[]));
+
+        let mut specs = vec![odd, v6, ed, rsa, v4b, fake_v4, v4a];
+        assert!(!link_specs_are_canonical(&specs));
+        sort_link_specs(&mut specs);
+        assert_eq!(specs, canonical);
+    }
 }
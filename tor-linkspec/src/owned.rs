@@ -0,0 +1,207 @@
+//! Owned variants of [`ChanTarget`] and [`CircTarget`](crate::CircTarget).
+
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tor_llcrypto::pk;
+
+use crate::{ChanTarget, CircTarget};
+
+/// A [`ChanTarget`] that holds owned copies of all of its information.
+///
+/// This is useful when we need to remember a relay after the directory
+/// that told us about it has gone away: for example, when we persist
+/// the list of relays we're using as guards.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnedChanTarget {
+    /// Addresses at which this relay can be reached.
+    addrs: Vec<SocketAddr>,
+    /// Ed25519 identity for this relay.
+    ed_identity: pk::ed25519::Ed25519Identity,
+    /// RSA identity for this relay.
+    rsa_identity: pk::rsa::RsaIdentity,
+}
+
+impl OwnedChanTarget {
+    /// Construct a new OwnedChanTarget from its parts.
+    pub fn new(
+        addrs: Vec<SocketAddr>,
+        ed_identity: pk::ed25519::Ed25519Identity,
+        rsa_identity: pk::rsa::RsaIdentity,
+    ) -> Self {
+        OwnedChanTarget {
+            addrs,
+            ed_identity,
+            rsa_identity,
+        }
+    }
+
+    /// Construct an OwnedChanTarget by copying the information from
+    /// some other ChanTarget.
+    pub fn from_chan_target<C>(target: &C) -> Self
+    where
+        C: ChanTarget + ?Sized,
+    {
+        OwnedChanTarget {
+            addrs: target.addrs().to_vec(),
+            ed_identity: *target.ed_identity(),
+            rsa_identity: *target.rsa_identity(),
+        }
+    }
+}
+
+impl ChanTarget for OwnedChanTarget {
+    fn addrs(&self) -> &[SocketAddr] {
+        &self.addrs[..]
+    }
+    fn ed_identity(&self) -> &pk::ed25519::Ed25519Identity {
+        &self.ed_identity
+    }
+    fn rsa_identity(&self) -> &pk::rsa::RsaIdentity {
+        &self.rsa_identity
+    }
+}
+
+/// A [`CircTarget`] that holds owned copies of all of its information.
+///
+/// Like [`OwnedChanTarget`], this can be persisted: in human-readable
+/// formats, addresses are written as strings, the RSA identity as hex,
+/// and the Ed25519 identity and ntor onion key as base64.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OwnedCircTarget {
+    /// The information needed to connect to this relay.
+    #[serde(flatten)]
+    chan_target: OwnedChanTarget,
+    /// The ntor onion key for this relay.
+    #[serde(with = "ntor_key_serde")]
+    ntor_onion_key: pk::curve25519::PublicKey,
+    /// The subprotocols implemented by this relay.
+    #[serde(with = "protovers_serde")]
+    protovers: tor_protover::Protocols,
+}
+
+impl OwnedCircTarget {
+    /// Construct a new OwnedCircTarget from its parts.
+    pub fn new(
+        chan_target: OwnedChanTarget,
+        ntor_onion_key: pk::curve25519::PublicKey,
+        protovers: tor_protover::Protocols,
+    ) -> Self {
+        OwnedCircTarget {
+            chan_target,
+            ntor_onion_key,
+            protovers,
+        }
+    }
+
+    /// Construct an OwnedCircTarget by copying the information from
+    /// some other CircTarget.
+    pub fn from_circ_target<C>(target: &C) -> Self
+    where
+        C: CircTarget + ?Sized,
+    {
+        OwnedCircTarget {
+            chan_target: OwnedChanTarget::from_chan_target(target),
+            ntor_onion_key: *target.ntor_onion_key(),
+            protovers: target.protovers().clone(),
+        }
+    }
+}
+
+impl ChanTarget for OwnedCircTarget {
+    fn addrs(&self) -> &[SocketAddr] {
+        self.chan_target.addrs()
+    }
+    fn ed_identity(&self) -> &pk::ed25519::Ed25519Identity {
+        self.chan_target.ed_identity()
+    }
+    fn rsa_identity(&self) -> &pk::rsa::RsaIdentity {
+        self.chan_target.rsa_identity()
+    }
+}
+
+impl CircTarget for OwnedCircTarget {
+    fn ntor_onion_key(&self) -> &pk::curve25519::PublicKey {
+        &self.ntor_onion_key
+    }
+    fn protovers(&self) -> &tor_protover::Protocols {
+        &self.protovers
+    }
+}
+
+/// Serde helpers to encode a curve25519 public key as base64.
+mod ntor_key_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use tor_llcrypto::pk::curve25519::PublicKey;
+
+    /// Serialize `key` as an unpadded base64 string.
+    pub(super) fn serialize<S: Serializer>(key: &PublicKey, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&base64::encode_config(
+            key.as_bytes(),
+            base64::STANDARD_NO_PAD,
+        ))
+    }
+
+    /// Deserialize a key from an unpadded base64 string.
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<PublicKey, D::Error> {
+        use serde::de::Error;
+        let s = String::deserialize(d)?;
+        let bytes = base64::decode_config(&s, base64::STANDARD_NO_PAD).map_err(D::Error::custom)?;
+        let bytes: [u8; 32] = std::convert::TryInto::try_into(&bytes[..])
+            .map_err(|_| D::Error::custom("wrong length for curve25519 public key"))?;
+        Ok(bytes.into())
+    }
+}
+
+/// Serde helpers to encode a list of subprotocols in its usual string
+/// form.
+mod protovers_serde {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use tor_protover::Protocols;
+
+    /// Serialize `protovers` as a string.
+    pub(super) fn serialize<S: Serializer>(protovers: &Protocols, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&protovers.to_string())
+    }
+
+    /// Deserialize a list of subprotocols from a string.
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Protocols, D::Error> {
+        use serde::de::Error;
+        String::deserialize(d)?.parse().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn circ_target_toml_roundtrip() {
+        let chan_target = OwnedChanTarget::new(
+            vec![
+                "127.0.0.1:9001".parse().unwrap(),
+                "[::1]:9001".parse().unwrap(),
+            ],
+            [42; 32].into(),
+            [45; 20].into(),
+        );
+        let key: pk::curve25519::PublicKey =
+            hex!("9d8c8a0ae14e1a2a3d94f8b6ff1c38d4a3b6bd36e14e5ef6e4a8fd6b37d0b222").into();
+        let target = OwnedCircTarget::new(
+            chan_target,
+            key,
+            "Link=1-5 LinkAuth=3 Relay=1-3".parse().unwrap(),
+        );
+
+        let s = toml::to_string(&target).unwrap();
+        assert!(s.contains("\"127.0.0.1:9001\""));
+        assert!(s.contains(&"2d".repeat(20)));
+        let back: OwnedCircTarget = toml::from_str(&s).unwrap();
+
+        assert_eq!(back.addrs(), target.addrs());
+        assert_eq!(back.ed_identity(), target.ed_identity());
+        assert_eq!(back.rsa_identity(), target.rsa_identity());
+        assert_eq!(back.ntor_onion_key().as_bytes(), key.as_bytes());
+        assert_eq!(back.protovers().to_string(), target.protovers().to_string());
+    }
+}
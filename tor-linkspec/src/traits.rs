@@ -4,7 +4,7 @@
 use std::net::SocketAddr;
 use tor_llcrypto::pk;
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
 /// Information about a Tor relay used to connect to it.
 ///
@@ -17,8 +17,28 @@ pub trait ChanTarget {
     // of addresses or references to addresses, but both of those options
     // make defining the right associated types rather tricky.
     fn addrs(&self) -> &[SocketAddr];
+    /// Return the number of addresses at which you can connect to
+    /// this relay.
+    fn num_addrs(&self) -> usize {
+        self.addrs().len()
+    }
+    /// Return true if any of this relay's addresses is an IPv6 address.
+    fn has_ipv6_addr(&self) -> bool {
+        self.addrs().iter().any(SocketAddr::is_ipv6)
+    }
     /// Return the ed25519 identity for this relay.
+    ///
+    /// Targets without an Ed25519 identity, such as some bridges,
+    /// should return an all-zero identity here.
     fn ed_identity(&self) -> &pk::ed25519::Ed25519Identity;
+    /// Return true if this relay can be authenticated by its Ed25519
+    /// identity.
+    ///
+    /// If this returns false, channel code should only check the
+    /// relay's RSA identity.
+    fn uses_ed25519_auth(&self) -> bool {
+        self.ed_identity().as_bytes() != [0_u8; 32]
+    }
     /// Return the ed25519 identity key for this relay, if it is valid.
     ///
     /// This can be costly.
@@ -29,6 +49,17 @@ pub trait ChanTarget {
     fn rsa_identity(&self) -> &pk::rsa::RsaIdentity;
 }
 
+impl TryFrom<&dyn ChanTarget> for pk::ed25519::Ed25519Identity {
+    type Error = crate::Error;
+    fn try_from(target: &dyn ChanTarget) -> Result<Self, crate::Error> {
+        if target.uses_ed25519_auth() {
+            Ok(*target.ed_identity())
+        } else {
+            Err(crate::Error::NoEd25519Identity)
+        }
+    }
+}
+
 /// Information about a Tor relay used to extend a circuit to it.
 ///
 /// Anything that implements 'CircTarget' can be used as the
@@ -52,6 +83,16 @@ pub trait CircTarget: ChanTarget {
     fn ntor_onion_key(&self) -> &pk::curve25519::PublicKey;
     /// Return the subprotocols implemented by this relay.
     fn protovers(&self) -> &tor_protover::Protocols;
+    /// Return the initial send window to use for streams that exit
+    /// from this relay.
+    ///
+    /// The default is 500 cells, which is what Tor uses.
+    /// Implementations may override this if they know better, for
+    /// example from this relay's `protovers()` or from a network
+    /// parameter.
+    fn stream_window_default(&self) -> u16 {
+        500
+    }
 }
 
 #[cfg(test)]
@@ -88,6 +129,22 @@ mod test {
         }
     }
 
+    #[test]
+    fn no_ed25519() {
+        let ex = Example {
+            addrs: vec![],
+            ed_id: pk::ed25519::Ed25519Identity::new([0; 32]),
+            rsa_id: pk::rsa::RsaIdentity::from_bytes(&[0x12; 20]).unwrap(),
+            ntor: pk::curve25519::PublicKey::from([0; 32]),
+            pv: tor_protover::Protocols::default(),
+        };
+        assert!(!ex.uses_ed25519_auth());
+        assert!(matches!(
+            pk::ed25519::Ed25519Identity::try_from(&ex as &dyn ChanTarget),
+            Err(crate::Error::NoEd25519Identity)
+        ));
+    }
+
     #[test]
     fn test_linkspecs() {
         let ex = Example {
@@ -112,6 +169,12 @@ mod test {
             pv: tor_protover::Protocols::default(),
         };
 
+        assert_eq!(ex.num_addrs(), 2);
+        assert!(ex.has_ipv6_addr());
+        assert!(ex.uses_ed25519_auth());
+        let ed_id = pk::ed25519::Ed25519Identity::try_from(&ex as &dyn ChanTarget).unwrap();
+        assert_eq!(ed_id, ex.ed_id);
+
         let specs = ex.linkspecs();
         assert_eq!(4, specs.len());
 
@@ -15,7 +15,7 @@ use curve25519_dalek::edwards::CompressedEdwardsY;
 use curve25519_dalek::scalar::Scalar;
 
 /// A relay's identity, as an unchecked, unvalidated Ed25519 key.
-#[derive(Clone, Copy, Hash)]
+#[derive(Clone, Copy, Hash, Ord, PartialOrd)]
 #[allow(clippy::derive_hash_xor_eq)]
 pub struct Ed25519Identity {
     /// A raw unchecked Ed25519 public key.
@@ -0,0 +1,84 @@
+//! Attach caller-specific data to relays.
+//!
+//! Guard managers, circuit managers, and similar code sometimes need to
+//! remember things about particular relays (whether they are a guard,
+//! when we last used them, and so on).  Rather than adding fields to
+//! [`NetDir`](crate::NetDir) or [`Relay`], they can keep that
+//! information in a [`RelayAnnotations`] keyed by relay identity.
+
+use crate::Relay;
+use std::collections::HashMap;
+use tor_llcrypto::pk::rsa::RsaIdentity;
+
+/// A map from relay RSA identities to values of type `T`.
+#[derive(Debug, Clone)]
+pub struct RelayAnnotations<T> {
+    /// The annotations themselves.
+    map: HashMap<RsaIdentity, T>,
+}
+
+impl<T> Default for RelayAnnotations<T> {
+    fn default() -> Self {
+        RelayAnnotations {
+            map: HashMap::new(),
+        }
+    }
+}
+
+impl<T> RelayAnnotations<T> {
+    /// Construct a new, empty set of annotations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the annotation for the relay with identity `id` to `value`.
+    ///
+    /// Return the previous annotation for that relay, if there was one.
+    pub fn insert(&mut self, id: RsaIdentity, value: T) -> Option<T> {
+        self.map.insert(id, value)
+    }
+
+    /// Return the annotation for the relay with identity `id`, if any.
+    pub fn get(&self, id: &RsaIdentity) -> Option<&T> {
+        self.map.get(id)
+    }
+
+    /// Remove and return the annotation for the relay with identity `id`,
+    /// if any.
+    pub fn remove(&mut self, id: &RsaIdentity) -> Option<T> {
+        self.map.remove(id)
+    }
+
+    /// Return the annotation for `relay`, if any.
+    pub fn get_for_relay(&self, relay: &Relay<'_>) -> Option<&T> {
+        self.map.get(relay.rsa_id())
+    }
+
+    /// Return a closure that looks up the annotation for a relay.
+    ///
+    /// This is convenient for building the predicates passed to
+    /// [`NetDir::pick_relay`](crate::NetDir::pick_relay).
+    pub fn as_relay_fn<'a, 'r>(&'a self) -> impl Fn(&Relay<'r>) -> Option<&'a T> + 'a {
+        move |relay| self.get_for_relay(relay)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn insert_get_remove() {
+        let id1: RsaIdentity = [1_u8; 20].into();
+        let id2: RsaIdentity = [2_u8; 20].into();
+
+        let mut ann = RelayAnnotations::new();
+        assert!(ann.get(&id1).is_none());
+        assert_eq!(ann.insert(id1, "guard"), None);
+        assert_eq!(ann.insert(id1, "primary guard"), Some("guard"));
+        assert_eq!(ann.get(&id1), Some(&"primary guard"));
+        assert!(ann.get(&id2).is_none());
+        assert_eq!(ann.remove(&id1), Some("primary guard"));
+        assert!(ann.get(&id1).is_none());
+    }
+}
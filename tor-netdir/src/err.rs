@@ -1,6 +1,7 @@
 //! Declare error type for tor-netdir
 
 use thiserror::Error;
+use tor_netdoc::doc::microdesc::MdDigest;
 
 /// An error returned by the network directory code
 #[derive(Error, Debug)]
@@ -32,3 +33,23 @@ pub enum Error {
     #[error("not enough directory information to build circuits")]
     NotEnoughInfo,
 }
+
+/// An error returned by [`NetDir::verify_coverage`](crate::NetDir::verify_coverage)
+/// when some relays in the consensus have no microdescriptor.
+#[derive(Error, Debug, Clone)]
+#[error("missing {} microdescriptors", .digests.len())]
+pub struct MissingMicrodescs {
+    /// The digests of the microdescriptors that we don't have.
+    digests: Vec<MdDigest>,
+}
+
+impl MissingMicrodescs {
+    /// Construct a new MissingMicrodescs for the listed digests.
+    pub(crate) fn new(digests: Vec<MdDigest>) -> Self {
+        MissingMicrodescs { digests }
+    }
+    /// Return the digests of the microdescriptors that we don't have.
+    pub fn digests(&self) -> &[MdDigest] {
+        &self.digests[..]
+    }
+}
@@ -11,9 +11,20 @@ use tor_llcrypto::pk::rsa::RsaIdentity;
 use serde::Deserialize;
 use std::net::SocketAddr;
 
+#[cfg(feature = "probing")]
+use {
+    std::time::Duration,
+    tor_chanmgr::ChanMgr,
+    tor_rtcompat::{Runtime, SleepProviderExt},
+};
+
 /// A directory whose location ships with Tor (or arti), and which we
 /// can use for bootstrapping when we don't know anything else about
 /// the network.
+///
+/// Two FallbackDirs are equal (and hash the same) if they have the same
+/// RSA identity, even if their Ed25519 identities or addresses differ.
+/// Use [`FallbackDir::is_same_relay`] to compare every field.
 //
 // Note that we do *not* set serde(deny_unknown_fields)] on this structure:
 // we want our authorities format to be future-proof against adding new info
@@ -41,6 +52,70 @@ impl FallbackDir {
             orports,
         }
     }
+
+    /// Return true if `self` and `other` agree on every field: their
+    /// RSA identity, their Ed25519 identity, and their addresses.
+    pub fn is_same_relay(&self, other: &FallbackDir) -> bool {
+        self.rsa_identity == other.rsa_identity
+            && self.ed_identity == other.ed_identity
+            && self.orports == other.orports
+    }
+
+    /// Return true if we can open a channel to this directory within
+    /// `timeout`.
+    ///
+    /// The channel comes from `chanmgr`, so if we already have an open
+    /// channel to this relay, it is reused and no new handshake is made.
+    ///
+    /// This function is only available if the crate was built with
+    /// its `probing` feature.
+    #[cfg(feature = "probing")]
+    pub async fn probe<R: Runtime>(
+        &self,
+        runtime: &R,
+        chanmgr: &ChanMgr<R>,
+        timeout: Duration,
+    ) -> bool {
+        matches!(
+            runtime.timeout(timeout, chanmgr.get_or_launch(self)).await,
+            Ok(Ok(_))
+        )
+    }
+}
+
+/// Probe every directory in `dirs` in parallel, and return the ones that
+/// were reachable within `timeout`, in their original order.
+///
+/// See [`FallbackDir::probe`].
+///
+/// This function is only available if the crate was built with its
+/// `probing` feature.
+#[cfg(feature = "probing")]
+pub async fn probe_all<'a, R: Runtime>(
+    dirs: &'a [FallbackDir],
+    runtime: &R,
+    chanmgr: &ChanMgr<R>,
+    timeout: Duration,
+) -> Vec<&'a FallbackDir> {
+    let probes = dirs.iter().map(|d| d.probe(runtime, chanmgr, timeout));
+    let reachable = futures::future::join_all(probes).await;
+    dirs.iter()
+        .zip(reachable)
+        .filter_map(|(d, ok)| if ok { Some(d) } else { None })
+        .collect()
+}
+
+impl PartialEq for FallbackDir {
+    fn eq(&self, other: &FallbackDir) -> bool {
+        self.rsa_identity == other.rsa_identity
+    }
+}
+impl Eq for FallbackDir {}
+
+impl std::hash::Hash for FallbackDir {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.rsa_identity.hash(state);
+    }
 }
 
 impl tor_linkspec::ChanTarget for FallbackDir {
@@ -54,3 +129,41 @@ impl tor_linkspec::ChanTarget for FallbackDir {
         &self.rsa_identity
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn eq_by_rsa_identity() {
+        let addr: SocketAddr = "127.0.0.1:9001".parse().unwrap();
+        let fb1 = FallbackDir::new([1; 20].into(), [2; 32].into(), vec![addr]);
+        let fb2 = FallbackDir::new([1; 20].into(), [3; 32].into(), vec![]);
+        let fb3 = FallbackDir::new([4; 20].into(), [2; 32].into(), vec![addr]);
+
+        assert_eq!(fb1, fb2);
+        assert!(!fb1.is_same_relay(&fb2));
+        assert!(fb1.is_same_relay(&fb1.clone()));
+        assert_ne!(fb1, fb3);
+
+        let set: HashSet<_> = vec![fb1, fb2, fb3].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[cfg(feature = "probing")]
+    #[test]
+    fn probe_unusable() {
+        // A fallback with no addresses can never be reached.
+        let fb = FallbackDir::new([1; 20].into(), [2; 32].into(), vec![]);
+        tor_rtcompat::test_with_runtime(|runtime| async move {
+            let chanmgr = ChanMgr::new(runtime.clone());
+            let timeout = Duration::from_secs(5);
+            assert!(!fb.probe(&runtime, &chanmgr, timeout).await);
+            let dirs = vec![fb.clone()];
+            assert!(probe_all(&dirs, &runtime, &chanmgr, timeout)
+                .await
+                .is_empty());
+        });
+    }
+}
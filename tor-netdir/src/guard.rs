@@ -0,0 +1,69 @@
+//! A persistable record of the relays we've chosen as guards.
+//!
+//! Guard state has to survive process restarts, so that a client keeps
+//! using the same small set of entry relays over time.  This module only
+//! defines the on-disk format; choosing and updating guards is up to the
+//! guard manager.
+
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, SystemTime};
+use tor_linkspec::OwnedChanTarget;
+
+/// A record of a set of guard relays, suitable for persistent storage.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuardSetRecord {
+    /// The relays that we have chosen as guards.
+    guards: Vec<OwnedChanTarget>,
+    /// The last time that this set of guards was changed.
+    last_updated: SystemTime,
+}
+
+impl GuardSetRecord {
+    /// Construct a new GuardSetRecord for `guards`, last updated at
+    /// `last_updated`.
+    pub fn new(guards: Vec<OwnedChanTarget>, last_updated: SystemTime) -> Self {
+        GuardSetRecord {
+            guards,
+            last_updated,
+        }
+    }
+
+    /// Return the guards listed in this record.
+    pub fn guards(&self) -> &[OwnedChanTarget] {
+        &self.guards[..]
+    }
+
+    /// Return the last time that this set of guards was changed.
+    pub fn last_updated(&self) -> SystemTime {
+        self.last_updated
+    }
+
+    /// Return true if this record is more than `max_age` old at `now`.
+    ///
+    /// A record that claims to have been updated in the future is not
+    /// considered stale.
+    pub fn is_stale(&self, now: SystemTime, max_age: Duration) -> bool {
+        match now.duration_since(self.last_updated) {
+            Ok(age) => age > max_age,
+            Err(_) => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn staleness() {
+        let t = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let rec = GuardSetRecord::new(Vec::new(), t);
+        let day = Duration::from_secs(86400);
+
+        assert!(!rec.is_stale(t, day));
+        assert!(!rec.is_stale(t + day, day));
+        assert!(rec.is_stale(t + day + Duration::from_secs(1), day));
+        // Records from the future are never stale.
+        assert!(!rec.is_stale(t - day, day));
+    }
+}
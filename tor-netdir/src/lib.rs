@@ -28,24 +28,32 @@
 #![deny(clippy::missing_docs_in_private_items)]
 #![warn(clippy::rc_buffer)]
 
+pub mod annotate;
 mod err;
 pub mod fallback;
+pub mod guard;
 pub mod params;
 mod pick;
+#[cfg(feature = "selection-telemetry")]
+pub mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testnet;
 mod weight;
 
 use ll::pk::rsa::RsaIdentity;
 use tor_llcrypto as ll;
 use tor_netdoc::doc::microdesc::{MdDigest, Microdesc};
 use tor_netdoc::doc::netstatus::{self, MdConsensus, RouterStatus};
-use tor_netdoc::types::policy::PortPolicy;
+use tor_netdoc::types::policy::{PortPolicy, PortRange};
 
+use futures::channel::mpsc;
 use log::warn;
-use std::collections::HashSet;
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
-pub use err::Error;
-pub use weight::WeightRole;
+pub use err::{Error, MissingMicrodescs};
+pub use weight::{BandwidthFn, RelayRole, WeightRole};
 /// A Result using the Error type from the tor-netdir crate
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -106,6 +114,142 @@ impl std::hash::Hash for MdEntry {
     }
 }
 
+/// Counter for allocating version numbers to NetDirs.
+///
+/// This is global so that a NetDir built from a new consensus always
+/// has a higher version than the one it replaces.
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// The ports allowed by the predefined "reduced" exit policy, as
+/// sorted, non-overlapping inclusive ranges.
+///
+/// This matches the `ReducedExitPolicy` option in C Tor.
+const REDUCED_EXIT_PORTS: &[(u16, u16)] = &[
+    (20, 23),
+    (43, 43),
+    (53, 53),
+    (79, 81),
+    (88, 88),
+    (110, 110),
+    (143, 143),
+    (194, 194),
+    (220, 220),
+    (389, 389),
+    (443, 443),
+    (464, 465),
+    (531, 531),
+    (543, 544),
+    (554, 554),
+    (563, 563),
+    (587, 587),
+    (636, 636),
+    (706, 706),
+    (749, 749),
+    (853, 853),
+    (873, 873),
+    (902, 904),
+    (981, 981),
+    (989, 995),
+    (1194, 1194),
+    (1220, 1220),
+    (1293, 1293),
+    (1500, 1500),
+    (1533, 1533),
+    (1677, 1677),
+    (1723, 1723),
+    (1755, 1755),
+    (1863, 1863),
+    (2082, 2083),
+    (2086, 2087),
+    (2095, 2096),
+    (2102, 2104),
+    (3128, 3128),
+    (3389, 3389),
+    (3690, 3690),
+    (4321, 4321),
+    (4643, 4643),
+    (5050, 5050),
+    (5190, 5190),
+    (5222, 5223),
+    (5228, 5228),
+    (5900, 5900),
+    (6660, 6669),
+    (6679, 6679),
+    (6697, 6697),
+    (8000, 8000),
+    (8008, 8008),
+    (8074, 8074),
+    (8080, 8080),
+    (8082, 8082),
+    (8087, 8088),
+    (8232, 8233),
+    (8332, 8333),
+    (8443, 8443),
+    (8888, 8888),
+    (9418, 9418),
+    (9999, 10000),
+    (11371, 11371),
+    (19294, 19294),
+    (19638, 19638),
+    (50002, 50002),
+    (64738, 64738),
+];
+
+/// Return true if the predefined "reduced" exit policy allows `port`.
+fn reduced_exit_policy_allows_port(port: u16) -> bool {
+    REDUCED_EXIT_PORTS
+        .binary_search_by(|&(lo, hi)| {
+            if hi < port {
+                std::cmp::Ordering::Less
+            } else if lo > port {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+}
+
+/// Return a new, never-before-used NetDir version number.
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Return true if we can build at least `min_pct` percent of paths,
+/// given the fractions of guard, middle, and exit bandwidth (by
+/// weight) for which we have microdescriptors.
+///
+/// A path needs all three of its hops, so the fraction of paths we can
+/// build is the product of the three fractions: missing most of any one
+/// kind of bandwidth is enough to make us wait for more information.
+fn enough_paths(frac_guard: f64, frac_middle: f64, frac_exit: f64, min_pct: i32) -> bool {
+    let min_frac_paths = (min_pct as f64) / 100.0;
+
+    // What fraction of paths can we build?
+    let available = frac_guard * frac_middle * frac_exit;
+
+    available >= min_frac_paths
+}
+
+/// Compute the network parameters for `consensus`, overridden by
+/// `replacement_params` if it is provided.
+fn compute_params(
+    consensus: &MdConsensus,
+    replacement_params: Option<&netstatus::NetParams<i32>>,
+) -> NetParameters {
+    let mut params = NetParameters::default();
+    params.update(consensus.params());
+    // We have to do this now, or else changes won't be reflected in our
+    // weights.
+    if let Some(replacement) = replacement_params {
+        let unrecognized = params.update(replacement);
+        for u in unrecognized {
+            warn!("Unrecognized option: override_net_params.{}", u);
+        }
+    }
+    params
+}
+
 /// A view of the Tor directory, suitable for use in building
 /// circuits.
 #[derive(Debug, Clone)]
@@ -119,18 +263,126 @@ pub struct NetDir {
     params: NetParameters,
     /// Map from SHA256 digest of microdescriptors to the
     /// microdescriptors themselves.
-    mds: HashSet<MdEntry>,
+    ///
+    /// This is shared between clones of a NetDir, so that cloning a
+    /// complete directory doesn't copy every entry.  Code that modifies
+    /// it uses `Arc::make_mut`, which only copies if the set is shared.
+    mds: Arc<HashSet<MdEntry>>,
     /// Weight values to apply to a given relay when deciding how frequently
     /// to choose it for a given role.
     weights: weight::WeightSet,
+    /// A version number for this NetDir, which changes whenever its
+    /// consensus is replaced or a microdescriptor is added.
+    version: u64,
+    /// Precomputed tables for picking routers from the consensus by
+    /// weight, one for each [`WeightRole`].
+    ///
+    /// These are only built once the directory is complete; until
+    /// then, we pick relays with a linear scan.
+    alias_tables: Option<Arc<[Option<pick::AliasTable>; N_WEIGHT_ROLES]>>,
+    /// Map from RSA identity to position in the consensus.
+    ///
+    /// Like `alias_tables`, this is only built once the directory is
+    /// complete.
+    rsa_idx: Option<Arc<HashMap<RsaIdentity, usize>>>,
+    /// Map from Ed25519 identity to position in the consensus, for the
+    /// relays whose microdescriptors we have.
+    ///
+    /// Like `alias_tables`, this is only built once the directory is
+    /// complete.
+    ed_idx: Option<Arc<HashMap<ll::pk::ed25519::Ed25519Identity, usize>>>,
 }
 
+/// Return the value inside `arc`, cloning it if `arc` is shared.
+fn unwrap_or_clone<T: Clone>(arc: Arc<T>) -> T {
+    Arc::try_unwrap(arc).unwrap_or_else(|shared| (*shared).clone())
+}
+
+/// The number of distinct [`WeightRole`]s.
+const N_WEIGHT_ROLES: usize = 5;
+
+/// Return a distinct index less than [`N_WEIGHT_ROLES`] for `role`.
+fn weight_role_index(role: WeightRole) -> usize {
+    match role {
+        WeightRole::Guard => 0,
+        WeightRole::Middle => 1,
+        WeightRole::Exit => 2,
+        WeightRole::BeginDir => 3,
+        WeightRole::Unweighted => 4,
+    }
+}
+
+/// How many times will we try picking from an alias table before
+/// falling back to a linear scan?
+///
+/// If the caller's `usable` predicate rejects most relays, repeated
+/// picks would rarely succeed, so we give up quickly.
+const ALIAS_PICK_ATTEMPTS: usize = 16;
+
 /// A partially build NetDir -- it can't be unwrapped until it has
 /// enough information to build safe paths.
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct PartialNetDir {
     /// The netdir that's under construction.
     netdir: NetDir,
+    /// Network parameters that override the ones in the consensus.
+    param_overrides: Option<netstatus::NetParams<i32>>,
+    /// Channels to notify when a microdescriptor is added.
+    subscribers: Vec<mpsc::Sender<MdAddedEvent>>,
+}
+
+impl Clone for PartialNetDir {
+    /// Clone this PartialNetDir.
+    ///
+    /// Subscribers are not carried over: they only hear about
+    /// microdescriptors added to the original.
+    fn clone(&self) -> Self {
+        PartialNetDir {
+            netdir: self.netdir.clone(),
+            param_overrides: self.param_overrides.clone(),
+            subscribers: Vec::new(),
+        }
+    }
+}
+
+/// A [`PartialNetDir`] that can be shared between several tasks.
+///
+/// Clones of a SharedPartialNetDir all refer to the same directory.
+/// Each operation takes a lock on it, so several download workers can
+/// add microdescriptors at once.
+///
+/// Create one with [`PartialNetDir::into_shared`].
+#[derive(Debug, Clone)]
+pub struct SharedPartialNetDir {
+    /// The directory under construction.
+    inner: Arc<Mutex<PartialNetDir>>,
+}
+
+/// Number of events that can wait in a [`PartialNetDir::subscribe`]
+/// channel before we start dropping them.
+const MD_EVENT_QUEUE_LEN: usize = 256;
+
+/// An event sent to subscribers of a [`PartialNetDir`] when a wanted
+/// microdescriptor is added.
+#[derive(Debug, Clone)]
+pub struct MdAddedEvent {
+    /// The digest of the microdescriptor that was added.
+    digest: MdDigest,
+    /// True if the directory now has enough information to build
+    /// multihop paths.
+    now_sufficient: bool,
+}
+
+impl MdAddedEvent {
+    /// Return the digest of the microdescriptor that was added.
+    pub fn digest(&self) -> &MdDigest {
+        &self.digest
+    }
+    /// Return true if the directory had enough information to build
+    /// multihop paths once this microdescriptor was added.
+    pub fn now_sufficient(&self) -> bool {
+        self.now_sufficient
+    }
 }
 
 /// A view of a relay on the Tor network, suitable for building circuits.
@@ -143,6 +395,82 @@ pub struct Relay<'a> {
     md: &'a Microdesc,
 }
 
+/// An IP address family, for checking exit policies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IpAddrFamily {
+    /// IPv4.
+    V4,
+    /// IPv6.
+    V6,
+}
+
+impl IpAddrFamily {
+    /// Return the family of `addr`.
+    pub fn of(addr: &std::net::IpAddr) -> Self {
+        match addr {
+            std::net::IpAddr::V4(_) => IpAddrFamily::V4,
+            std::net::IpAddr::V6(_) => IpAddrFamily::V6,
+        }
+    }
+}
+
+/// A structured view of which ports a relay will exit to.
+///
+/// The accepted and rejected ranges are sorted, disjoint, and together
+/// cover every port from 1 through 65535.
+///
+/// Like the [`PortPolicy`] it comes from, this only tells you whether
+/// a port is _probably_ allowed: see the documentation there.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitPolicySummary {
+    /// The port ranges that the relay accepts.
+    accepted: Vec<PortRange>,
+    /// The port ranges that the relay rejects.
+    rejected: Vec<PortRange>,
+}
+
+impl ExitPolicySummary {
+    /// Construct a summary from a sorted, disjoint list of accepted
+    /// ranges, treating every other port as rejected.
+    fn from_accepted(accepted: Vec<PortRange>) -> Self {
+        let mut rejected = Vec::new();
+        let mut next_lo: u32 = 1;
+        for r in accepted.iter() {
+            if u32::from(r.lo) > next_lo {
+                rejected.extend(PortRange::new(next_lo as u16, r.lo - 1));
+            }
+            next_lo = u32::from(r.hi) + 1;
+        }
+        if next_lo <= 65535 {
+            rejected.extend(PortRange::new(next_lo as u16, 65535));
+        }
+        ExitPolicySummary { accepted, rejected }
+    }
+    /// Return the port ranges that the relay accepts.
+    pub fn accepted(&self) -> &[PortRange] {
+        &self.accepted[..]
+    }
+    /// Return the port ranges that the relay rejects.
+    pub fn rejected(&self) -> &[PortRange] {
+        &self.rejected[..]
+    }
+    /// Return an iterator over every port that the relay accepts, in
+    /// ascending order.
+    pub fn allowed_ports(&self) -> impl Iterator<Item = u16> + '_ {
+        self.accepted.iter().flat_map(|r| r.lo..=r.hi)
+    }
+}
+
+/// A reason to be wary of using a relay, even though it is usable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RelayWarning {
+    /// The relay has reported that it is overloaded, either in general
+    /// or because it is hitting its rate limit.
+    Overloaded,
+}
+
 /// A relay that we haven't checked for validity or usability in
 /// routing.
 struct UncheckedRelay<'a> {
@@ -152,6 +480,17 @@ struct UncheckedRelay<'a> {
     md: Option<&'a Microdesc>,
 }
 
+/// The outcome of offering a microdescriptor to an [`MdReceiver`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MdAddResult {
+    /// The microdescriptor was wanted, and has been added.
+    Accepted,
+    /// The microdescriptor wasn't wanted: either we never asked for it,
+    /// or we already have it.
+    Unwanted,
+}
+
 /// A partial or full network directory that we can download
 /// microdescriptors for.
 pub trait MdReceiver {
@@ -162,6 +501,18 @@ pub trait MdReceiver {
     ///
     /// Return true if it was indeed wanted.
     fn add_microdesc(&mut self, md: Microdesc) -> bool;
+    /// Add a microdescriptor to this netdir, if it was wanted, and say
+    /// what happened to it.
+    ///
+    /// This is like [`MdReceiver::add_microdesc`], but with room for
+    /// more outcomes than "wanted" and "unwanted" as we add checks.
+    fn try_add_microdesc(&mut self, md: Microdesc) -> MdAddResult {
+        if self.add_microdesc(md) {
+            MdAddResult::Accepted
+        } else {
+            MdAddResult::Unwanted
+        }
+    }
 }
 
 impl PartialNetDir {
@@ -174,16 +525,7 @@ impl PartialNetDir {
         consensus: MdConsensus,
         replacement_params: Option<&netstatus::NetParams<i32>>,
     ) -> Self {
-        let mut params = NetParameters::default();
-        params.update(consensus.params());
-        // We have to do this now, or else changes won't be reflected in our
-        // weights.
-        if let Some(replacement) = replacement_params {
-            let unrecognized = params.update(replacement);
-            for u in unrecognized {
-                warn!("Unrecognized option: override_net_params.{}", u);
-            }
-        }
+        let params = compute_params(&consensus, replacement_params);
 
         // Compute the weights we'll want to use for these routers.
         let weights = weight::WeightSet::from_consensus(&consensus, &params);
@@ -191,14 +533,83 @@ impl PartialNetDir {
         let mut netdir = NetDir {
             consensus: Arc::new(consensus),
             params,
-            mds: HashSet::new(),
+            mds: Arc::new(HashSet::new()),
             weights,
+            version: next_version(),
+            alias_tables: None,
+            rsa_idx: None,
+            ed_idx: None,
         };
 
         for rs in netdir.consensus.routers().iter() {
-            netdir.mds.insert(MdEntry::Absent(*rs.md_digest()));
+            Arc::make_mut(&mut netdir.mds).insert(MdEntry::Absent(*rs.md_digest()));
+        }
+        PartialNetDir {
+            netdir,
+            param_overrides: replacement_params.cloned(),
+            subscribers: Vec::new(),
+        }
+    }
+
+    /// Create a new PartialNetDir with a given consensus, pre-filled
+    /// with every microdescriptor from `old_netdir` that the consensus
+    /// still lists.
+    ///
+    /// After a consensus update, this usually leaves only a few
+    /// microdescriptors to fetch.
+    pub fn from_cached_netdir(consensus: MdConsensus, old_netdir: &NetDir) -> Self {
+        let mut partial = PartialNetDir::new(consensus, None);
+        partial.fill_from_previous_netdir(old_netdir);
+        partial
+    }
+
+    /// Replace the consensus in this PartialNetDir with `new_consensus`,
+    /// keeping every microdescriptor that the new consensus still lists.
+    ///
+    /// Return the old consensus, along with the digests of the
+    /// microdescriptors (present or not) that the new consensus no
+    /// longer lists.
+    ///
+    /// Network parameter overrides given to [`PartialNetDir::new`] are
+    /// applied to the new consensus too.
+    pub fn swap_consensus(&mut self, new_consensus: MdConsensus) -> (MdConsensus, Vec<MdDigest>) {
+        let params = compute_params(&new_consensus, self.param_overrides.as_ref());
+        let weights = weight::WeightSet::from_consensus(&new_consensus, &params);
+
+        let mut old_mds = unwrap_or_clone(std::mem::take(&mut self.netdir.mds));
+        let mut mds = HashSet::new();
+        for rs in new_consensus.routers().iter() {
+            let ent = old_mds
+                .take(rs.md_digest())
+                .unwrap_or(MdEntry::Absent(*rs.md_digest()));
+            mds.insert(ent);
         }
-        PartialNetDir { netdir }
+        let unneeded = old_mds.iter().map(|ent| *ent.digest()).collect();
+
+        let old_consensus = std::mem::replace(&mut self.netdir.consensus, Arc::new(new_consensus));
+        self.netdir.params = params;
+        self.netdir.mds = Arc::new(mds);
+        self.netdir.weights = weights;
+        self.netdir.version = next_version();
+        self.netdir.alias_tables = None;
+        self.netdir.rsa_idx = None;
+        self.netdir.ed_idx = None;
+
+        let old_consensus = Arc::try_unwrap(old_consensus).unwrap_or_else(|c| (*c).clone());
+        (old_consensus, unneeded)
+    }
+
+    /// Return a channel that receives an [`MdAddedEvent`] every time
+    /// `add_microdesc` adds a wanted microdescriptor to this directory.
+    ///
+    /// To keep `add_microdesc` from blocking, events are discarded if
+    /// the receiver falls too far behind.  Callers that only care
+    /// whether the directory is usable can check
+    /// [`PartialNetDir::have_enough_paths`] after a gap.
+    pub fn subscribe(&mut self) -> mpsc::Receiver<MdAddedEvent> {
+        let (tx, rx) = mpsc::channel(MD_EVENT_QUEUE_LEN);
+        self.subscribers.push(tx);
+        rx
     }
 
     /// Return the declared lifetime of this PartialNetDir.
@@ -213,12 +624,167 @@ impl PartialNetDir {
             if let MdEntry::Present(md) = ent {
                 if self.netdir.mds.contains(md.digest()) {
                     loaded.push(md.digest());
-                    self.netdir.mds.replace(ent.clone());
+                    Arc::make_mut(&mut self.netdir.mds).replace(ent.clone());
                 }
             }
         }
+        if !loaded.is_empty() {
+            self.netdir.version = next_version();
+        }
         loaded
     }
+    /// Copy every microdescriptor from `old` that this directory is
+    /// still missing, and return copies of the ones we copied.
+    ///
+    /// This is useful when re-bootstrapping after a short outage: most
+    /// microdescriptors are usually unchanged, and after this call
+    /// they no longer appear in `missing_microdescs()`.  Unlike
+    /// [`PartialNetDir::fill_from_previous_netdir`], this only counts
+    /// microdescriptors we were missing, and it tells our subscribers
+    /// about each one.
+    pub fn microdescs_to_reuse(&mut self, old: &NetDir) -> Vec<Microdesc> {
+        let mut reused = Vec::new();
+        for ent in old.mds.iter() {
+            if let MdEntry::Present(md) = ent {
+                if matches!(self.netdir.mds.get(md.digest()), Some(MdEntry::Absent(_))) {
+                    Arc::make_mut(&mut self.netdir.mds).replace(ent.clone());
+                    reused.push(Microdesc::clone(md));
+                }
+            }
+        }
+        if !reused.is_empty() {
+            self.netdir.version = next_version();
+        }
+        for md in reused.iter() {
+            self.notify_added(*md.digest());
+        }
+        reused
+    }
+    /// Add `md` to this directory, even if we already have it or
+    /// don't want it.
+    ///
+    /// This replaces any microdescriptor we already had with the same
+    /// digest.  It lets tests inject synthetic microdescriptors with
+    /// exactly the properties they need.
+    ///
+    /// Return true if any relay in the consensus uses `md`.
+    ///
+    /// This function is only available if the crate was built with
+    /// its `testing` feature.
+    #[cfg(feature = "testing")]
+    pub fn force_add_microdesc(&mut self, md: Microdesc) -> bool {
+        let digest = *md.digest();
+        let used = self
+            .netdir
+            .consensus
+            .routers()
+            .iter()
+            .any(|rs| rs.md_digest() == &digest);
+        Arc::make_mut(&mut self.netdir.mds).replace(md.into());
+        self.netdir.version = next_version();
+        self.notify_added(digest);
+        used
+    }
+    /// Copy every microdescriptor from `other` that this directory is
+    /// still missing.
+    ///
+    /// This is meant for combining directories that were built from
+    /// the same consensus, for example by downloading from several
+    /// caches at once.  Microdescriptors that this directory doesn't
+    /// want are ignored.
+    ///
+    /// Return the number of missing microdescriptors that were filled.
+    pub fn merge_from(&mut self, other: PartialNetDir) -> usize {
+        let mut filled = Vec::new();
+        for ent in unwrap_or_clone(other.netdir.mds) {
+            if let MdEntry::Present(md) = &ent {
+                if matches!(self.netdir.mds.get(md.digest()), Some(MdEntry::Absent(_))) {
+                    filled.push(*md.digest());
+                    Arc::make_mut(&mut self.netdir.mds).replace(ent);
+                }
+            }
+        }
+        if !filled.is_empty() {
+            self.netdir.version = next_version();
+        }
+        for d in filled.iter() {
+            self.notify_added(*d);
+        }
+        filled.len()
+    }
+    /// Tell our subscribers that we've added the microdescriptor with
+    /// digest `digest`.
+    fn notify_added(&mut self, digest: MdDigest) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+        let event = MdAddedEvent {
+            digest,
+            now_sufficient: self.netdir.have_enough_paths(),
+        };
+        for tx in self.subscribers.iter_mut() {
+            // If the channel is full, this event is dropped.
+            let _ = tx.try_send(event.clone());
+        }
+        // Forget about any subscribers that have gone away.
+        self.subscribers.retain(|tx| !tx.is_closed());
+    }
+    /// Return a reference to the (possibly incomplete) NetDir inside
+    /// this PartialNetDir.
+    ///
+    /// This is useful for operations that don't need a complete
+    /// directory, like reading network parameters or listing relays
+    /// for diagnostic purposes.
+    ///
+    /// # Warning
+    ///
+    /// The returned NetDir might not satisfy `have_enough_paths()`:
+    /// don't use it to build circuits.
+    pub fn as_incomplete_netdir(&self) -> &NetDir {
+        &self.netdir
+    }
+    /// Return an iterator over the digests of microdescriptors that we
+    /// are holding, but that no relay in the current consensus uses.
+    pub fn excess_microdescs(&self) -> impl Iterator<Item = &MdDigest> + '_ {
+        let wanted: HashSet<&MdDigest> = self
+            .netdir
+            .consensus
+            .routers()
+            .iter()
+            .map(|rs| rs.md_digest())
+            .collect();
+        self.netdir
+            .mds
+            .iter()
+            .map(MdEntry::digest)
+            .filter(move |d| !wanted.contains(d))
+    }
+    /// Discard every microdescriptor listed by
+    /// [`PartialNetDir::excess_microdescs`].
+    ///
+    /// Return the number of microdescriptors discarded.
+    pub fn remove_excess_microdescs(&mut self) -> usize {
+        let excess: Vec<MdDigest> = self.excess_microdescs().copied().collect();
+        for d in excess.iter() {
+            Arc::make_mut(&mut self.netdir.mds).remove(d);
+        }
+        excess.len()
+    }
+    /// Return the fraction of the consensus bandwidth that is on
+    /// relays we can use, because we have their microdescriptors.
+    ///
+    /// The result is between 0.0 and 1.0, and is suitable for reporting
+    /// bootstrap progress.
+    pub fn fraction_available(&self) -> f64 {
+        self.netdir.fraction_available()
+    }
+    /// Return the number of relays in the consensus that we can use,
+    /// and the number of relays in the consensus.
+    pub fn relay_counts(&self) -> (usize, usize) {
+        let total = self.netdir.consensus.routers().len();
+        let have = self.netdir.relays().count();
+        (have, total)
+    }
     /// Return true if this are enough information in this directory
     /// to build multihop paths.
     pub fn have_enough_paths(&self) -> bool {
@@ -226,13 +792,63 @@ impl PartialNetDir {
     }
     /// If this directory has enough information to build multihop
     /// circuits, return it.
+    ///
+    /// This is also when we precompute the tables that make picking
+    /// relays fast.
     pub fn unwrap_if_sufficient(self) -> std::result::Result<NetDir, PartialNetDir> {
         if self.netdir.have_enough_paths() {
-            Ok(self.netdir)
+            let mut netdir = self.netdir;
+            netdir.build_alias_tables();
+            netdir.build_id_indexes();
+            Ok(netdir)
         } else {
             Err(self)
         }
     }
+    /// Wrap this directory so that it can be shared between tasks.
+    pub fn into_shared(self) -> SharedPartialNetDir {
+        SharedPartialNetDir {
+            inner: Arc::new(Mutex::new(self)),
+        }
+    }
+}
+
+impl SharedPartialNetDir {
+    /// Return the digests for all of the microdescriptors that this
+    /// directory is missing.
+    ///
+    /// Unlike [`MdReceiver::missing_microdescs`], this returns a copy,
+    /// since we can't hold the lock while the caller looks at them.
+    pub fn missing_microdescs(&self) -> Vec<MdDigest> {
+        let dir = self.inner.lock().unwrap();
+        dir.missing_microdescs().copied().collect()
+    }
+    /// Add a microdescriptor to this directory, if it was wanted.
+    ///
+    /// Return true if it was indeed wanted.
+    pub fn add_microdesc(&self, md: Microdesc) -> bool {
+        self.inner.lock().unwrap().add_microdesc(md)
+    }
+    /// Add a microdescriptor to this directory, if it was wanted, and
+    /// say what happened to it.
+    pub fn try_add_microdesc(&self, md: Microdesc) -> MdAddResult {
+        self.inner.lock().unwrap().try_add_microdesc(md)
+    }
+    /// Return true if there is enough information in this directory
+    /// to build multihop paths.
+    pub fn have_enough_paths(&self) -> bool {
+        self.inner.lock().unwrap().have_enough_paths()
+    }
+    /// Return the PartialNetDir inside this wrapper.
+    ///
+    /// Fails, returning the wrapper, if there are other clones of it
+    /// still around.
+    pub fn try_into_inner(self) -> std::result::Result<PartialNetDir, SharedPartialNetDir> {
+        match Arc::try_unwrap(self.inner) {
+            Ok(mutex) => Ok(mutex.into_inner().unwrap()),
+            Err(inner) => Err(SharedPartialNetDir { inner }),
+        }
+    }
 }
 
 impl MdReceiver for PartialNetDir {
@@ -240,7 +856,12 @@ impl MdReceiver for PartialNetDir {
         self.netdir.missing_microdescs()
     }
     fn add_microdesc(&mut self, md: Microdesc) -> bool {
-        self.netdir.add_microdesc(md)
+        let digest = *md.digest();
+        let added = self.netdir.add_microdesc(md);
+        if added {
+            self.notify_added(digest);
+        }
+        added
     }
 }
 
@@ -250,6 +871,25 @@ impl NetDir {
         &self.consensus.lifetime()
     }
 
+    /// Return the SHA256 digest of the signed part of the consensus
+    /// that this NetDir was built from.
+    ///
+    /// This stays the same when microdescriptors are added, so it can
+    /// be used as a cache key, or to identify the base of a consensus
+    /// diff.
+    pub fn consensus_digest(&self) -> [u8; 32] {
+        *self.consensus.signed_sha256()
+    }
+
+    /// Return a version number for this NetDir.
+    ///
+    /// The version increases every time a microdescriptor is added,
+    /// or the consensus is replaced with a new one.  Callers can
+    /// compare versions to detect whether a directory has changed.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
     /// Construct a (possibly invalid) Relay object from a routerstatus and its
     /// microdescriptor (if any).
     fn relay_from_rs<'a>(
@@ -276,6 +916,29 @@ impl NetDir {
     pub fn relays(&self) -> impl Iterator<Item = Relay<'_>> {
         self.all_relays().filter_map(UncheckedRelay::into_relay)
     }
+    /// Return an iterator over all usable Relays that are suitable for
+    /// use as guards, according to this directory's parameters.
+    ///
+    /// See [`Relay::is_suitable_guard`].
+    pub fn suitable_guards(&self) -> impl Iterator<Item = Relay<'_>> {
+        self.relays()
+            .filter(move |r| r.is_suitable_guard(&self.params))
+    }
+    /// Return the RSA identities of all usable Relays, in sorted order.
+    ///
+    /// This is mainly useful for comparing the relay lists of two
+    /// directories, as in regression tests.
+    pub fn relay_rsa_ids(&self) -> BTreeSet<RsaIdentity> {
+        self.relays().map(|r| *r.rsa_id()).collect()
+    }
+    /// Return the Ed25519 identities of all usable Relays, in sorted
+    /// order.
+    ///
+    /// This is mainly useful for comparing the relay lists of two
+    /// directories, as in regression tests.
+    pub fn relay_ed25519_ids(&self) -> BTreeSet<ll::pk::ed25519::Ed25519Identity> {
+        self.relays().map(|r| *r.id()).collect()
+    }
     /// Return the parameters from the consensus, clamped to the
     /// correct ranges, with defaults filled in.
     ///
@@ -284,6 +947,28 @@ impl NetDir {
     pub fn params(&self) -> &NetParameters {
         &self.params
     }
+    /// Return the function this directory uses to find the base
+    /// bandwidth of each relay from its consensus weight.
+    ///
+    /// This is the same function that [`NetDir::pick_relay`] uses, so
+    /// code that does its own relay selection can weight relays
+    /// consistently with ours.
+    pub fn weight_fn(&self) -> BandwidthFn {
+        self.weights.bandwidth_fn()
+    }
+    /// Return the fraction of the consensus bandwidth that is on
+    /// relays we can use, because we have their microdescriptors.
+    ///
+    /// See [`PartialNetDir::fraction_available`].
+    pub fn fraction_available(&self) -> f64 {
+        let frac = self.frac_for_role(WeightRole::Unweighted);
+        if frac.is_nan() {
+            // There's no bandwidth in the consensus at all.
+            0.0
+        } else {
+            frac
+        }
+    }
     /// Return the fraction of total bandwidth weight for a given role
     /// that we have available information for in this NetDir.
     fn frac_for_role(&self, role: WeightRole) -> f64 {
@@ -300,6 +985,34 @@ impl NetDir {
 
         (have_weight as f64) / (total_weight as f64)
     }
+    /// Return the number of usable relays that allow exiting to `port`
+    /// over IPv4.
+    pub fn exit_count_for_port(&self, port: u16) -> usize {
+        self.relays()
+            .filter(|r| r.supports_exit_port_ipv4(port))
+            .count()
+    }
+    /// Return the fraction of usable exit bandwidth, weighted as for
+    /// [`WeightRole::Exit`], that is on relays allowing exits to `port`
+    /// over IPv4.
+    ///
+    /// Return 0.0 if no relay allows exits to `port`.
+    pub fn exit_fraction_for_port(&self, port: u16) -> f64 {
+        let mut total_weight = 0_u64;
+        let mut port_weight = 0_u64;
+        for r in self.relays() {
+            let w = self.weights.weight_rs_for_role(r.rs, WeightRole::Exit);
+            total_weight += w;
+            if r.supports_exit_port_ipv4(port) {
+                port_weight += w;
+            }
+        }
+        if port_weight == 0 {
+            0.0
+        } else {
+            (port_weight as f64) / (total_weight as f64)
+        }
+    }
     /// Return true if there is enough information in this NetDir to build
     /// multihop circuits.
     fn have_enough_paths(&self) -> bool {
@@ -307,14 +1020,147 @@ impl NetDir {
         // probability, we know enough information to participate
         // on the network.
         let min_pct = self.params().get(Param::MinPathsForCircsPct);
-        let min_frac_paths = (min_pct as f64) / 100.0;
-
-        // What fraction of paths can we build?
-        let available = self.frac_for_role(WeightRole::Guard)
-            * self.frac_for_role(WeightRole::Middle)
-            * self.frac_for_role(WeightRole::Exit);
 
-        available >= min_frac_paths
+        enough_paths(
+            self.frac_for_role(WeightRole::Guard),
+            self.frac_for_role(WeightRole::Middle),
+            self.frac_for_role(WeightRole::Exit),
+            min_pct,
+        )
+    }
+    /// Precompute an alias table for every [`WeightRole`], indexed by
+    /// position in the consensus.
+    ///
+    /// The tables depend only on the consensus and its weights, so
+    /// they stay valid when we add microdescriptors later.
+    fn build_alias_tables(&mut self) {
+        let routers = self.consensus.routers();
+        let table_for = |role| {
+            let weights: Vec<u64> = routers
+                .iter()
+                .map(|rs| self.weights.weight_rs_for_role(rs, role))
+                .collect();
+            pick::AliasTable::new(&weights)
+        };
+        self.alias_tables = Some(Arc::new([
+            table_for(WeightRole::Guard),
+            table_for(WeightRole::Middle),
+            table_for(WeightRole::Exit),
+            table_for(WeightRole::BeginDir),
+            table_for(WeightRole::Unweighted),
+        ]));
+    }
+    /// Build the maps from relay identities to consensus positions.
+    fn build_id_indexes(&mut self) {
+        let mut rsa_idx = HashMap::new();
+        let mut ed_idx = HashMap::new();
+        for (pos, rs) in self.consensus.routers().iter().enumerate() {
+            rsa_idx.insert(*rs.rsa_identity(), pos);
+            if let Some(MdEntry::Present(md)) = self.mds.get(rs.md_digest()) {
+                ed_idx.insert(*md.ed25519_id(), pos);
+            }
+        }
+        self.rsa_idx = Some(Arc::new(rsa_idx));
+        self.ed_idx = Some(Arc::new(ed_idx));
+    }
+    /// Check that we have a microdescriptor for every relay in the
+    /// consensus.
+    ///
+    /// This is a sanity check for debugging and tests: a usable NetDir
+    /// can still be missing some microdescriptors.  In release builds
+    /// it does nothing, and always returns `Ok(())`.
+    pub fn verify_coverage(&self) -> std::result::Result<(), MissingMicrodescs> {
+        #[cfg(debug_assertions)]
+        {
+            let missing: Vec<MdDigest> = self.missing_microdescs().copied().collect();
+            if !missing.is_empty() {
+                return Err(MissingMicrodescs::new(missing));
+            }
+        }
+        Ok(())
+    }
+    /// Return true if this directory has a usable relay with the RSA
+    /// identity `id`.
+    ///
+    /// This is cheaper than looking the relay up, once the directory is
+    /// complete.
+    pub fn has_relay_with_rsa_id(&self, id: &RsaIdentity) -> bool {
+        match &self.rsa_idx {
+            Some(idx) => idx.get(id).is_some_and(|pos| self.is_usable_at(*pos)),
+            None => self.relays().any(|r| r.rsa_id() == id),
+        }
+    }
+    /// Return true if this directory has a usable relay with the
+    /// Ed25519 identity `id`.
+    ///
+    /// This is cheaper than looking the relay up, once the directory is
+    /// complete.
+    pub fn has_relay_with_ed25519_id(&self, id: &ll::pk::ed25519::Ed25519Identity) -> bool {
+        match &self.ed_idx {
+            Some(idx) => idx.get(id).is_some_and(|pos| self.is_usable_at(*pos)),
+            None => self.relays().any(|r| r.id() == id),
+        }
+    }
+    /// Return the usable relay with the Ed25519 identity `id`, if there
+    /// is one.
+    ///
+    /// Once the directory is complete, this uses an index rather than
+    /// scanning every relay.
+    pub fn relay_by_id(&self, id: &ll::pk::ed25519::Ed25519Identity) -> Option<Relay<'_>> {
+        match &self.ed_idx {
+            Some(idx) => idx.get(id).and_then(|pos| self.relay_at(*pos)),
+            None => self.relays().find(|r| r.id() == id),
+        }
+    }
+    /// Return the usable relay with the RSA identity `id`, if there is
+    /// one.
+    ///
+    /// Once the directory is complete, this uses an index rather than
+    /// scanning every relay.
+    pub fn relay_by_rsa_id(&self, id: &RsaIdentity) -> Option<Relay<'_>> {
+        match &self.rsa_idx {
+            Some(idx) => idx.get(id).and_then(|pos| self.relay_at(*pos)),
+            None => self.relays().find(|r| r.rsa_id() == id),
+        }
+    }
+    /// Return an iterator over the usable relays in `old_netdir` that
+    /// are not listed in this directory's consensus.
+    ///
+    /// Relays are matched by RSA identity.  This is meant for finding
+    /// cached information about relays that have left the network.
+    pub fn relays_not_in_consensus<'a>(
+        &self,
+        old_netdir: &'a NetDir,
+    ) -> impl Iterator<Item = Relay<'a>> {
+        let listed: HashSet<RsaIdentity> = self
+            .consensus
+            .routers()
+            .iter()
+            .map(|rs| *rs.rsa_identity())
+            .collect();
+        old_netdir
+            .relays()
+            .filter(move |r| !listed.contains(r.rsa_id()))
+    }
+    /// Return true if the relay at position `pos` in the consensus is
+    /// usable.
+    fn is_usable_at(&self, pos: usize) -> bool {
+        self.relay_at(pos).is_some()
+    }
+    /// Return the relay at position `pos` in the consensus, if it is
+    /// usable.
+    fn relay_at(&self, pos: usize) -> Option<Relay<'_>> {
+        self.consensus
+            .routers()
+            .get(pos)
+            .and_then(|rs| self.relay_from_rs(rs).into_relay())
+    }
+    /// Return true if the relays `a` and `b` should not appear together
+    /// on the same circuit.
+    ///
+    /// See [`Relay::in_same_family`] for the rules we apply.
+    pub fn are_in_same_family(&self, a: &Relay<'_>, b: &Relay<'_>) -> bool {
+        a.in_same_family(b)
     }
     /// Chose a relay at random.
     ///
@@ -324,6 +1170,10 @@ impl NetDir {
     ///
     /// This function returns None if (and only if) there are no relays
     /// with nonzero weight where `usable` returned true.
+    ///
+    /// This function does not exclude relays in the same family as one
+    /// another: callers building a path are responsible for doing so in
+    /// `usable`, using [`NetDir::are_in_same_family`].
     pub fn pick_relay<'a, R, P>(
         &'a self,
         rng: &mut R,
@@ -334,6 +1184,25 @@ impl NetDir {
         R: rand::Rng,
         P: Fn(&Relay<'a>) -> bool,
     {
+        // If we have a precomputed table, pick from it until we find a
+        // usable relay.  Rejecting unusable picks this way gives the
+        // same distribution as the linear scan below.
+        let table = self
+            .alias_tables
+            .as_ref()
+            .and_then(|t| t[weight_role_index(role)].as_ref());
+        if let Some(table) = table {
+            let routers = self.consensus.routers();
+            for _ in 0..ALIAS_PICK_ATTEMPTS {
+                let rs = &routers[table.pick(rng)];
+                if let Some(r) = self.relay_from_rs(rs).into_relay() {
+                    if usable(&r) {
+                        return Some(r);
+                    }
+                }
+            }
+        }
+
         pick::pick_weighted(rng, self.relays(), |r| {
             if usable(r) {
                 self.weights.weight_rs_for_role(&r.rs, role)
@@ -342,6 +1211,160 @@ impl NetDir {
             }
         })
     }
+    /// Return an iterator over all usable relays, paired with their
+    /// weights for `role`, in descending order of weight.
+    ///
+    /// Relays are sorted lazily, so taking the first few from the
+    /// iterator is cheaper than sorting all of them.  This is meant for
+    /// deterministic selection strategies, such as picking the top K
+    /// relays; use [`NetDir::pick_relay`] for random selection.
+    pub fn weighted_relay_iter(
+        &self,
+        role: WeightRole,
+    ) -> impl Iterator<Item = (Relay<'_>, u64)> + '_ {
+        let mut heap: std::collections::BinaryHeap<(u64, std::cmp::Reverse<usize>)> = self
+            .consensus
+            .routers()
+            .iter()
+            .enumerate()
+            .filter(|(_, rs)| self.relay_from_rs(rs).is_usable())
+            .map(|(pos, rs)| {
+                let w = self.weights.weight_rs_for_role(rs, role);
+                // Break ties by consensus order.
+                (w, std::cmp::Reverse(pos))
+            })
+            .collect();
+        let routers = self.consensus.routers();
+        std::iter::from_fn(move || heap.pop()).filter_map(move |(w, std::cmp::Reverse(pos))| {
+            self.relay_from_rs(&routers[pos])
+                .into_relay()
+                .map(|r| (r, w))
+        })
+    }
+    /// Chose a relay at random to use in the position `role`.
+    ///
+    /// Relays that aren't suitable for `role` are never picked; the
+    /// others are chosen with probability proportional to their
+    /// bandwidth, adjusted by the consensus bandwidth weights for that
+    /// position.  This is the recommended way to pick a relay: use
+    /// [`NetDir::pick_relay`] only when you need your own predicate.
+    ///
+    /// Return None if there is no suitable relay.
+    pub fn pick_relay_by_role<'a, R>(&'a self, rng: &mut R, role: RelayRole) -> Option<Relay<'a>>
+    where
+        R: rand::Rng,
+    {
+        let weight_role = role.weight_role();
+        match role {
+            RelayRole::Guard => self.pick_relay(rng, weight_role, |r| r.rs.is_flagged_guard()),
+            RelayRole::Exit { port } => {
+                self.pick_relay(rng, weight_role, |r| r.supports_exit_port_ipv4(port))
+            }
+            RelayRole::Middle | RelayRole::Rendezvous => {
+                self.pick_relay(rng, weight_role, |_| true)
+            }
+        }
+    }
+    /// Chose a relay at random, as [`NetDir::pick_relay`] does, and tell
+    /// `telemetry` about the relay that was chosen for `position`.
+    ///
+    /// This function is only available if the crate was built with its
+    /// `selection-telemetry` feature.
+    #[cfg(feature = "selection-telemetry")]
+    pub fn pick_relay_with_telemetry<'a, R, P, T>(
+        &'a self,
+        rng: &mut R,
+        role: WeightRole,
+        usable: P,
+        telemetry: &T,
+        position: telemetry::CircuitPosition,
+    ) -> Option<Relay<'a>>
+    where
+        R: rand::Rng,
+        P: Fn(&Relay<'a>) -> bool,
+        T: telemetry::SelectionTelemetry + ?Sized,
+    {
+        let relay = self.pick_relay(rng, role, usable)?;
+        telemetry.relay_selected(&relay, position);
+        Some(relay)
+    }
+    /// Chose a relay at random, never picking any relay whose RSA
+    /// identity is listed in `excluded`.
+    ///
+    /// This behaves like [`NetDir::pick_relay`], except that relays
+    /// in `excluded` are treated as unusable.
+    pub fn pick_relay_excluding<'a, R, P>(
+        &'a self,
+        rng: &mut R,
+        role: WeightRole,
+        excluded: &[RsaIdentity],
+        usable: P,
+    ) -> Option<Relay<'a>>
+    where
+        R: rand::Rng,
+        P: Fn(&Relay<'a>) -> bool,
+    {
+        self.pick_relay(rng, role, |r| !excluded.contains(r.rsa_id()) && usable(r))
+    }
+    /// Chose a relay at random, using a random number generator seeded
+    /// with `seed`.
+    ///
+    /// This behaves like [`NetDir::pick_relay`], except that for a given
+    /// directory and seed it always makes the same choice.  It is meant
+    /// for tests, and must not be used for real path selection.
+    pub fn pick_relay_with_seed<'a, P>(
+        &'a self,
+        seed: u64,
+        role: WeightRole,
+        usable: P,
+    ) -> Option<Relay<'a>>
+    where
+        P: Fn(&Relay<'a>) -> bool,
+    {
+        use rand::SeedableRng;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        self.pick_relay(&mut rng, role, usable)
+    }
+    /// Return every usable relay together with its score, sorted
+    /// from highest score to lowest.
+    ///
+    /// The score for each relay is `score_fn(relay, bw)`, where `bw` is
+    /// the relay's bandwidth weight as listed in the consensus,
+    /// whether measured or not.  Relays with equal scores stay in
+    /// consensus order.
+    pub fn relay_scores<F>(&self, score_fn: F) -> Vec<(Relay<'_>, u64)>
+    where
+        F: Fn(&Relay<'_>, u32) -> u64,
+    {
+        let mut scored: Vec<(Relay<'_>, u64)> = self
+            .relays()
+            .map(|r| {
+                let bw = match r.rs.weight() {
+                    netstatus::RouterWeight::Measured(w)
+                    | netstatus::RouterWeight::Unmeasured(w) => *w,
+                    _ => 0,
+                };
+                let score = score_fn(&r, bw);
+                (r, score)
+            })
+            .collect();
+        scored.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+        scored
+    }
+    /// Return the usable relay whose Ed25519 identity is `target_id`,
+    /// for use as the next hop of a circuit through `current_path`.
+    ///
+    /// Return None if there is no such relay, or if it is already on
+    /// `current_path`, as identified by RSA identity.
+    pub fn relay_for_extension<'a>(
+        &'a self,
+        target_id: &ll::pk::ed25519::Ed25519Identity,
+        current_path: &[&RsaIdentity],
+    ) -> Option<Relay<'a>> {
+        self.relays()
+            .find(|r| r.id() == target_id)
+            .filter(|r| !current_path.contains(&r.rsa_id()))
+    }
 }
 
 impl MdReceiver for NetDir {
@@ -355,9 +1378,19 @@ impl MdReceiver for NetDir {
         }))
     }
     fn add_microdesc(&mut self, md: Microdesc) -> bool {
+        let digest = *md.digest();
+        let ed_id = *md.ed25519_id();
         let ent = md.into();
-        if self.mds.remove(&ent) {
-            self.mds.insert(ent);
+        let mds = Arc::make_mut(&mut self.mds);
+        if mds.remove(&ent) {
+            mds.insert(ent);
+            self.version = next_version();
+            if let Some(idx) = &mut self.ed_idx {
+                let routers = self.consensus.routers();
+                if let Some(pos) = routers.iter().position(|rs| rs.md_digest() == &digest) {
+                    Arc::make_mut(idx).insert(ed_id, pos);
+                }
+            }
             true
         } else {
             false
@@ -372,11 +1405,37 @@ impl<'a> UncheckedRelay<'a> {
     /// to the user.
     fn is_usable(&self) -> bool {
         // No need to check for 'valid' or 'running': they are implicit.
-        self.md.is_some() && self.rs.ed25519_id_is_usable()
+        match self.md {
+            Some(md) => self.md_matches_rs(md) && self.rs.ed25519_id_is_usable(),
+            None => false,
+        }
+    }
+    /// Return true if `md` is the microdescriptor that our routerstatus
+    /// lists.
+    ///
+    /// A microdescriptor consensus doesn't list relays' Ed25519
+    /// identities: the md digest is what binds the Ed25519 identity in
+    /// `md` to this routerstatus.  We only look up microdescriptors by
+    /// that digest, so this should never fail; if it does, something
+    /// has gone wrong with our data.
+    fn md_matches_rs(&self, md: &Microdesc) -> bool {
+        if md.digest() == self.rs.md_digest() {
+            true
+        } else {
+            warn!(
+                "Microdescriptor for relay {} doesn't match its consensus entry; ignoring the relay.",
+                self.rs.rsa_identity()
+            );
+            false
+        }
     }
     /// If this is usable, return a corresponding Relay object.
     fn into_relay(self) -> Option<Relay<'a>> {
         if self.is_usable() {
+            // is_usable() relies on these flags being implicit in a
+            // consensus; make sure that stays true.
+            debug_assert!(self.rs.is_flagged_running());
+            debug_assert!(self.rs.is_flagged_valid());
             Some(Relay {
                 rs: self.rs,
                 md: self.md?,
@@ -385,6 +1444,14 @@ impl<'a> UncheckedRelay<'a> {
             None
         }
     }
+    /// Return a list of reasons to be wary of using this relay.
+    fn warnings(&self) -> Vec<RelayWarning> {
+        let mut warnings = Vec::new();
+        if self.rs.is_flagged_overload_general() || self.rs.is_flagged_overload_rate_limit() {
+            warnings.push(RelayWarning::Overloaded);
+        }
+        warnings
+    }
 }
 
 impl<'a> Relay<'a> {
@@ -396,19 +1463,170 @@ impl<'a> Relay<'a> {
     pub fn rsa_id(&self) -> &RsaIdentity {
         self.rs.rsa_identity()
     }
+    /// Return the ntor onion key for this relay.
+    ///
+    /// This is the same as [`CircTarget::ntor_onion_key`](tor_linkspec::CircTarget::ntor_onion_key),
+    /// but doesn't need the trait in scope.
+    pub fn ntor_onion_key_direct(&self) -> &ll::pk::curve25519::PublicKey {
+        self.md.ntor_key()
+    }
+    /// Return the subprotocols implemented by this relay.
+    ///
+    /// This is the same as [`CircTarget::protovers`](tor_linkspec::CircTarget::protovers),
+    /// but doesn't need the trait in scope.
+    pub fn protovers_direct(&self) -> &tor_protover::Protocols {
+        self.rs.protovers()
+    }
     /// Return true if this relay and `other` seem to be the same relay.
     ///
     /// (Two relays are the same if they have the same identity.)
     pub fn same_relay<'b>(&self, other: &Relay<'b>) -> bool {
         self.id() == other.id() && self.rsa_id() == other.rsa_id()
     }
+    /// Return true if this relay is listed as running.
+    ///
+    /// Every relay in a consensus is implicitly running, so this is
+    /// always true for a `Relay`.
+    pub fn is_running(&self) -> bool {
+        self.rs.is_flagged_running()
+    }
+    /// Return true if this relay is listed as valid.
+    ///
+    /// Every relay in a consensus is implicitly valid, so this is
+    /// always true for a `Relay`.
+    pub fn is_valid(&self) -> bool {
+        self.rs.is_flagged_valid()
+    }
+    /// Return the time after which we should consider this relay's
+    /// ntor onion key to be stale, if we can tell.
+    ///
+    /// Microdescriptors don't say when their onion keys expire, so we
+    /// estimate this as the descriptor's published time plus the
+    /// longest time that relays keep accepting an onion key: the
+    /// 28-day rotation period plus a 7-day grace period.
+    pub fn ntor_key_valid_until(&self) -> Option<std::time::SystemTime> {
+        /// How long a relay keeps accepting an onion key after
+        /// publishing it.
+        const NTOR_KEY_LIFETIME: std::time::Duration =
+            std::time::Duration::from_secs((28 + 7) * 24 * 60 * 60);
+        self.rs.published().checked_add(NTOR_KEY_LIFETIME)
+    }
+    /// Return the time at which this relay's router descriptor was
+    /// published, according to the consensus.
+    ///
+    /// For now this always returns a value; it returns an Option
+    /// because authorities may stop sending meaningful publication
+    /// times (see proposal 275).
+    pub fn published_at(&self) -> Option<std::time::SystemTime> {
+        Some(self.rs.published())
+    }
+    /// Return a rough lower bound on how long this relay has been up,
+    /// as of `now`.
+    ///
+    /// This is just the time since the relay's descriptor was
+    /// published, which C Tor also uses as a proxy.  It is not the real
+    /// uptime: the relay could have been down, or restarted, since then
+    /// without publishing a new descriptor.  If the publication time is
+    /// after `now`, this is zero.
+    pub fn uptime_estimate(&self, now: std::time::SystemTime) -> Option<std::time::Duration> {
+        let published = self.published_at()?;
+        Some(now.duration_since(published).unwrap_or_default())
+    }
+    /// Return the first IPv4 address listed for this relay, if any.
+    pub fn ipv4_addr(&self) -> Option<std::net::Ipv4Addr> {
+        self.rs.addrs().iter().find_map(|a| match a {
+            std::net::SocketAddr::V4(a) => Some(*a.ip()),
+            _ => None,
+        })
+    }
+    /// Return the first IPv6 address listed for this relay, if any.
+    pub fn ipv6_addr(&self) -> Option<std::net::Ipv6Addr> {
+        self.rs.addrs().iter().find_map(|a| match a {
+            std::net::SocketAddr::V6(a) => Some(*a.ip()),
+            _ => None,
+        })
+    }
+    /// Return an iterator over the IPv4 addresses and ports listed
+    /// for this relay.
+    pub fn ipv4_socket_addrs(&self) -> impl Iterator<Item = (std::net::Ipv4Addr, u16)> + 'a {
+        self.rs.addrs().iter().filter_map(|a| match a {
+            std::net::SocketAddr::V4(a) => Some((*a.ip(), a.port())),
+            _ => None,
+        })
+    }
+    /// Return an iterator over the IPv6 addresses and ports listed
+    /// for this relay.
+    pub fn ipv6_socket_addrs(&self) -> impl Iterator<Item = (std::net::Ipv6Addr, u16)> + 'a {
+        self.rs.addrs().iter().filter_map(|a| match a {
+            std::net::SocketAddr::V6(a) => Some((*a.ip(), a.port())),
+            _ => None,
+        })
+    }
+    /// Return an iterator over all the addresses listed for this relay,
+    /// with the IPv6 addresses first.
+    ///
+    /// Clients with IPv6 connectivity should try these in order.
+    pub fn socket_addresses_preferring_ipv6(
+        &self,
+    ) -> impl Iterator<Item = &'a std::net::SocketAddr> + 'a {
+        let addrs = self.rs.addrs();
+        let v6 = addrs.iter().filter(|a| a.is_ipv6());
+        let v4 = addrs.iter().filter(|a| a.is_ipv4());
+        v6.chain(v4)
+    }
     /// Return true if this relay allows exiting to `port` on IPv4.
+    ///
+    /// Relays with the ReducedExitPolicy flag are checked against the
+    /// predefined reduced policy instead of their own.
     pub fn supports_exit_port_ipv4(&self, port: u16) -> bool {
-        !self.rs.is_flagged_bad_exit() && self.md.ipv4_policy().allows_port(port)
+        if self.rs.is_flagged_bad_exit() {
+            return false;
+        }
+        if self.rs.is_flagged_reduced_exit_policy() {
+            return reduced_exit_policy_allows_port(port);
+        }
+        self.md.ipv4_policy().allows_port(port)
     }
     /// Return true if this relay allows exiting to `port` on IPv6.
+    ///
+    /// Relays with the ReducedExitPolicy flag are checked against the
+    /// predefined reduced policy instead of their own.
     pub fn supports_exit_port_ipv6(&self, port: u16) -> bool {
-        !self.rs.is_flagged_bad_exit() && self.md.ipv6_policy().allows_port(port)
+        if self.rs.is_flagged_bad_exit() {
+            return false;
+        }
+        if self.rs.is_flagged_reduced_exit_policy() {
+            return reduced_exit_policy_allows_port(port);
+        }
+        self.md.ipv6_policy().allows_port(port)
+    }
+    /// Return true if this relay allows exiting to `port` on addresses
+    /// of the given `family`.
+    ///
+    /// Use this when you already know the family of the destination
+    /// address: an IPv6 destination needs an exit with a matching IPv6
+    /// policy.
+    pub fn supports_exit_port_on(&self, port: u16, family: IpAddrFamily) -> bool {
+        match family {
+            IpAddrFamily::V4 => self.supports_exit_port_ipv4(port),
+            IpAddrFamily::V6 => self.supports_exit_port_ipv6(port),
+        }
+    }
+    /// Return true if this relay is suitable for use as a guard, given
+    /// the network parameters in `params`.
+    ///
+    /// A suitable guard has the Guard flag, doesn't have the BadExit
+    /// flag, and has a consensus bandwidth weight of at least
+    /// [`Param::GuardMinBandwidth`](params::Param::GuardMinBandwidth).
+    pub fn is_suitable_guard(&self, params: &NetParameters) -> bool {
+        let bw = match self.rs.weight() {
+            netstatus::RouterWeight::Measured(w) | netstatus::RouterWeight::Unmeasured(w) => *w,
+            _ => 0,
+        };
+        let min_bw = params.get(Param::GuardMinBandwidth);
+        self.rs.is_flagged_guard()
+            && !self.rs.is_flagged_bad_exit()
+            && i64::from(bw) >= i64::from(min_bw)
     }
     /// Return true if this relay is suitable for use as a directory
     /// cache.
@@ -422,16 +1640,73 @@ impl<'a> Relay<'a> {
     }
     /// Return true if both relays are in the same family.
     ///
-    /// (Every relay is considered to be in the same family as itself.)
+    /// Two relays are in the same family if each lists the other in its
+    /// family, or if they have public IPv4 addresses in the same /16
+    /// network.  (Every relay is considered to be in the same family as
+    /// itself.)
     pub fn in_same_family<'b>(&self, other: &Relay<'b>) -> bool {
         // XXX: features missing from original implementation:
-        // - option EnforceDistinctSubnets
         // - option NodeFamilySets
+        // - making EnforceDistinctSubnets configurable
         // see: src/feature/nodelist/nodelist.c:nodes_in_same_family()
+        //
+        // Like Tor, we ignore private, loopback and link-local
+        // addresses when comparing subnets, so that test networks on a
+        // single LAN still work.
         if self.same_relay(other) {
             return true;
         }
-        self.md.family().contains(other.rsa_id()) && other.md.family().contains(self.rsa_id())
+        if self.md.family().contains(other.rsa_id()) && other.md.family().contains(self.rsa_id()) {
+            return true;
+        }
+        addrs_in_same_ipv4_16(self.rs.addrs(), other.rs.addrs())
+    }
+
+    /// Return true if this relay has reported that it is overloaded in
+    /// general.
+    ///
+    /// Overloaded relays should be avoided, or given a lower weight,
+    /// when choosing paths.
+    pub fn is_overloaded_general(&self) -> bool {
+        self.rs.is_flagged_overload_general()
+    }
+    /// Return true if this relay has reported that it is hitting its
+    /// bandwidth rate limit.
+    ///
+    /// Overloaded relays should be avoided, or given a lower weight,
+    /// when choosing paths.
+    pub fn is_overloaded_ratelimit(&self) -> bool {
+        self.rs.is_flagged_overload_rate_limit()
+    }
+    /// Return a list of reasons to be wary of using this relay.
+    ///
+    /// An empty list means that we know of no reason to avoid it.
+    pub fn warnings(&self) -> Vec<RelayWarning> {
+        UncheckedRelay {
+            rs: self.rs,
+            md: Some(self.md),
+        }
+        .warnings()
+    }
+
+    /// Return a summary of the ports that this relay allows exiting
+    /// to on IPv4.
+    ///
+    /// This agrees with [`Relay::supports_exit_port_ipv4`]: a BadExit
+    /// relay rejects every port, and a relay with the ReducedExitPolicy
+    /// flag is described by the predefined reduced policy.
+    pub fn exit_policy_summary(&self) -> ExitPolicySummary {
+        let accepted = if self.rs.is_flagged_bad_exit() {
+            Vec::new()
+        } else if self.rs.is_flagged_reduced_exit_policy() {
+            REDUCED_EXIT_PORTS
+                .iter()
+                .filter_map(|&(lo, hi)| PortRange::new(lo, hi))
+                .collect()
+        } else {
+            self.md.ipv4_policy().allowed_ranges().to_vec()
+        };
+        ExitPolicySummary::from_accepted(accepted)
     }
 
     /// Return the IPv4 exit policy for this relay.
@@ -485,9 +1760,458 @@ impl<'a> tor_linkspec::ChanTarget for Relay<'a> {
 
 impl<'a> tor_linkspec::CircTarget for Relay<'a> {
     fn ntor_onion_key(&self) -> &ll::pk::curve25519::PublicKey {
-        self.md.ntor_key()
+        self.ntor_onion_key_direct()
     }
     fn protovers(&self) -> &tor_protover::Protocols {
-        self.rs.protovers()
+        self.protovers_direct()
+    }
+}
+
+/// Return true if any public IPv4 address in `a` is in the same /16
+/// network as any public IPv4 address in `b`.
+fn addrs_in_same_ipv4_16(a: &[std::net::SocketAddr], b: &[std::net::SocketAddr]) -> bool {
+    use std::net::IpAddr;
+    /// Return the /16 network prefix of `addr`, if it is a public IPv4
+    /// address.
+    fn prefix(addr: &std::net::SocketAddr) -> Option<[u8; 2]> {
+        match addr.ip() {
+            IpAddr::V4(ip) if ip.is_private() || ip.is_loopback() || ip.is_link_local() => None,
+            IpAddr::V4(ip) => {
+                let o = ip.octets();
+                Some([o[0], o[1]])
+            }
+            IpAddr::V6(_) => None,
+        }
+    }
+    a.iter()
+        .filter_map(prefix)
+        .any(|pa| b.iter().filter_map(prefix).any(|pb| pa == pb))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::{FutureExt, StreamExt};
+
+    #[test]
+    fn same_ipv4_16() {
+        let addrs = |v: &[&str]| -> Vec<std::net::SocketAddr> {
+            v.iter().map(|s| s.parse().unwrap()).collect()
+        };
+        let a = addrs(&["20.0.2.1:9001", "[2001:db8::1]:9001"]);
+        let b = addrs(&["20.0.99.7:443"]);
+        let c = addrs(&["20.1.2.1:9001", "[2001:db8::1]:9001"]);
+        assert!(addrs_in_same_ipv4_16(&a, &b));
+        assert!(addrs_in_same_ipv4_16(&b, &a));
+        // Sharing an IPv6 address doesn't count; only IPv4 /16s do.
+        assert!(!addrs_in_same_ipv4_16(&a, &c));
+        assert!(!addrs_in_same_ipv4_16(&b, &c));
+        assert!(!addrs_in_same_ipv4_16(&a, &[]));
+        // Addresses that aren't public don't count either.
+        for (x, y) in &[
+            ("10.0.0.1:9001", "10.0.5.5:9001"),
+            ("192.168.1.1:9001", "192.168.1.2:9001"),
+            ("127.0.0.1:9001", "127.0.0.2:9002"),
+            ("169.254.0.1:9001", "169.254.7.7:9001"),
+        ] {
+            assert!(!addrs_in_same_ipv4_16(&addrs(&[x]), &addrs(&[y])));
+        }
+    }
+
+    #[test]
+    fn same_family() {
+        let mut relays = testnet::default_relays();
+        // Relays 1 and 2 list each other; relay 3 lists relay 4, but
+        // not the other way around.
+        relays[1].family = vec![2];
+        relays[2].family = vec![1];
+        relays[3].family = vec![4];
+        // Relays 5 and 6 share a public /16; relays 7 and 8 share a
+        // private one.
+        relays[6].addr = "20.5.7.7".parse().unwrap();
+        relays[7].addr = "10.0.0.7".parse().unwrap();
+        relays[8].addr = "10.0.0.8".parse().unwrap();
+        let netdir = testnet::netdir(&relays);
+        let relay = |idx| netdir.relay_by_rsa_id(&testnet::rsa_id(idx)).unwrap();
+
+        assert!(netdir.are_in_same_family(&relay(0), &relay(0)));
+        assert!(netdir.are_in_same_family(&relay(1), &relay(2)));
+        assert!(netdir.are_in_same_family(&relay(2), &relay(1)));
+        assert!(!netdir.are_in_same_family(&relay(3), &relay(4)));
+        assert!(!netdir.are_in_same_family(&relay(4), &relay(3)));
+        assert!(netdir.are_in_same_family(&relay(5), &relay(6)));
+        assert!(!netdir.are_in_same_family(&relay(7), &relay(8)));
+        assert!(!netdir.are_in_same_family(&relay(0), &relay(11)));
+    }
+
+    #[test]
+    fn enough_paths_needs_every_role() {
+        assert!(enough_paths(1.0, 1.0, 1.0, 60));
+        assert!(enough_paths(0.9, 0.9, 0.9, 60));
+        // Having every middle and exit doesn't help if we are missing
+        // most of the guard bandwidth.
+        assert!(!enough_paths(0.5, 1.0, 1.0, 60));
+        assert!(!enough_paths(1.0, 1.0, 0.5, 60));
+        assert!(!enough_paths(0.0, 1.0, 1.0, 25));
+        // Small shortfalls in each role add up.
+        assert!(!enough_paths(0.8, 0.8, 0.8, 60));
+    }
+
+    #[test]
+    fn enough_paths_missing_guards() {
+        let mut relays = testnet::default_relays();
+        // Relay 9 is a guard with far more bandwidth than the rest.
+        relays[9].bandwidth = 50_000;
+        let mut mds = testnet::microdescs(&relays);
+        let md9 = mds.remove(9);
+        let mut partial = testnet::partial_netdir(&relays);
+        for md in mds {
+            partial.add_microdesc(md);
+        }
+        // We have every middle and exit, and all but one guard, but
+        // that isn't enough.
+        assert_eq!(partial.missing_microdescs().count(), 1);
+        assert!(!partial.have_enough_paths());
+        assert!(partial.fraction_available() < 1.0);
+        let mut partial = partial.unwrap_if_sufficient().unwrap_err();
+
+        assert!(partial.add_microdesc(md9));
+        assert!(partial.have_enough_paths());
+        let netdir = partial.unwrap_if_sufficient().unwrap();
+        assert_eq!(netdir.relays().count(), 12);
+        assert!((netdir.fraction_available() - 1.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn reduced_exit_policy() {
+        for w in REDUCED_EXIT_PORTS.windows(2) {
+            assert!(w[0].1 < w[1].0);
+        }
+        for port in &[20, 22, 23, 80, 443, 993, 6667, 10000, 64738] {
+            assert!(reduced_exit_policy_allows_port(*port));
+        }
+        for port in &[0, 19, 24, 25, 119, 445, 6881, 10001, 65535] {
+            assert!(!reduced_exit_policy_allows_port(*port));
+        }
+    }
+
+    #[test]
+    fn alias_pick_distribution() {
+        use rand::SeedableRng;
+        let netdir = testnet::netdir(&testnet::default_relays());
+        assert!(netdir.alias_tables.is_some());
+        // The same directory, but always picking with a linear scan.
+        let mut linear = netdir.clone();
+        linear.alias_tables = None;
+
+        // Only relays 2, 5, 8 and 11 have any weight as middles: the
+        // rest are guards or exits.  Rejecting relay 11 leaves the
+        // others in proportion to their bandwidths, 3:6:9.
+        let rejected = testnet::rsa_id(11);
+        let n_iters = 5000;
+        for dir in &[&netdir, &linear] {
+            let mut rng = rand::rngs::StdRng::from_seed(*b"A seed for picking middle relays");
+            let mut counts = HashMap::new();
+            for _ in 0..n_iters {
+                let r = dir
+                    .pick_relay(&mut rng, WeightRole::Middle, |r| r.rsa_id() != &rejected)
+                    .unwrap();
+                *counts.entry(*r.rsa_id()).or_insert(0_isize) += 1;
+            }
+            assert_eq!(counts.len(), 3);
+            for (idx, share) in &[(2, 3), (5, 6), (8, 9)] {
+                let expected = n_iters * share / 18;
+                let got = counts[&testnet::rsa_id(*idx)];
+                assert!((got - expected).abs() <= expected / 20 + 5);
+            }
+        }
+    }
+
+    #[test]
+    fn alias_pick_falls_back() {
+        let netdir = testnet::netdir(&testnet::default_relays());
+        let mut rng = rand::thread_rng();
+        // Relay 2 has the smallest middle weight, so picks from the
+        // alias table will usually be rejected until we fall back to a
+        // linear scan.
+        let wanted = testnet::rsa_id(2);
+        for _ in 0..100 {
+            let r = netdir
+                .pick_relay(&mut rng, WeightRole::Middle, |r| r.rsa_id() == &wanted)
+                .unwrap();
+            assert_eq!(r.rsa_id(), &wanted);
+        }
+        // Relay 0 is a guard, and has no weight as a middle.
+        let zero = testnet::rsa_id(0);
+        assert!(netdir
+            .pick_relay(&mut rng, WeightRole::Middle, |r| r.rsa_id() == &zero)
+            .is_none());
+    }
+
+    #[test]
+    fn subscribe_to_added_mds() {
+        let relays = testnet::default_relays();
+        let mut partial = testnet::partial_netdir(&relays);
+        let mut rx = partial.subscribe();
+        let closed = partial.subscribe();
+        drop(closed);
+
+        let mds = testnet::microdescs(&relays);
+        let n_mds = mds.len();
+        for md in mds.iter() {
+            assert!(partial.add_microdesc(md.clone()));
+        }
+        // Adding an unwanted microdescriptor doesn't send an event.
+        let mut more_relays = relays.clone();
+        more_relays.push(testnet::TestRelay::new(relays.len()));
+        let unwanted = testnet::microdescs(&more_relays).pop().unwrap();
+        assert!(!partial.add_microdesc(unwanted));
+        // We forgot about the subscriber that went away.
+        assert_eq!(partial.subscribers.len(), 1);
+
+        let mut events = Vec::new();
+        while let Some(Some(ev)) = rx.next().now_or_never() {
+            events.push(ev);
+        }
+        assert_eq!(events.len(), n_mds);
+        for (ev, md) in events.iter().zip(mds.iter()) {
+            assert_eq!(ev.digest(), md.digest());
+        }
+        assert!(!events[0].now_sufficient());
+        assert!(events[n_mds - 1].now_sufficient());
+
+        // Once the directory goes away, the channel closes.
+        drop(partial);
+        assert!(matches!(rx.next().now_or_never(), Some(None)));
+    }
+
+    #[test]
+    fn swap_consensus() {
+        let relays = testnet::default_relays();
+        let overrides = "circwindow=500".parse().unwrap();
+        let mut partial = PartialNetDir::new(testnet::consensus(&relays), Some(&overrides));
+        let old_mds = testnet::microdescs(&relays);
+        for md in old_mds.iter() {
+            partial.add_microdesc(md.clone());
+        }
+        let old_version = partial.netdir.version();
+
+        // In the new consensus, relay 1 has a new microdescriptor, and
+        // relay 12 is new.
+        let mut new_relays = relays.clone();
+        new_relays[1].exit_policy = "accept 443".into();
+        new_relays.push(testnet::TestRelay::new(12));
+        let new_mds = testnet::microdescs(&new_relays);
+
+        let (old, unneeded) = partial.swap_consensus(testnet::consensus(&new_relays));
+        assert_eq!(old.routers().len(), relays.len());
+        assert_eq!(unneeded, vec![*old_mds[1].digest()]);
+        let missing: HashSet<_> = partial.missing_microdescs().copied().collect();
+        let expected: HashSet<_> = [*new_mds[1].digest(), *new_mds[12].digest()]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(missing, expected);
+        // The overrides still apply.
+        assert_eq!(partial.netdir.params().get(Param::CircWindow), 500);
+        assert!(partial.netdir.version() > old_version);
+    }
+
+    #[test]
+    fn merge_from() {
+        let relays = testnet::default_relays();
+        let mds = testnet::microdescs(&relays);
+        let mut a = testnet::partial_netdir(&relays);
+        let mut b = testnet::partial_netdir(&relays);
+        for md in &mds[6..] {
+            a.add_microdesc(md.clone());
+        }
+        for md in &mds[..8] {
+            b.add_microdesc(md.clone());
+        }
+        assert!(!a.have_enough_paths());
+        // Relays 6 and 7 were already in `a`.
+        assert_eq!(a.merge_from(b), 6);
+        assert_eq!(a.missing_microdescs().count(), 0);
+        assert!(a.have_enough_paths());
+
+        let empty = testnet::partial_netdir(&relays);
+        assert_eq!(a.merge_from(empty), 0);
+    }
+
+    #[test]
+    fn from_cached_netdir() {
+        let relays = testnet::default_relays();
+        let old = testnet::netdir(&relays);
+
+        let mut new_relays = relays.clone();
+        new_relays[4].exit_policy = "accept 80".into();
+        let new_mds = testnet::microdescs(&new_relays);
+        let partial = PartialNetDir::from_cached_netdir(testnet::consensus(&new_relays), &old);
+        let missing: Vec<_> = partial.missing_microdescs().copied().collect();
+        assert_eq!(missing, vec![*new_mds[4].digest()]);
+        assert!(partial.have_enough_paths());
+    }
+
+    #[test]
+    fn version_changes() {
+        let relays = testnet::default_relays();
+        let mut partial = testnet::partial_netdir(&relays);
+        let mut last = partial.netdir.version();
+        for md in testnet::microdescs(&relays) {
+            partial.add_microdesc(md);
+            assert!(partial.netdir.version() > last);
+            last = partial.netdir.version();
+        }
+        // Adding a microdescriptor we don't want changes nothing.
+        let mut more_relays = relays.clone();
+        more_relays.push(testnet::TestRelay::new(relays.len()));
+        let unwanted = testnet::microdescs(&more_relays).pop().unwrap();
+        partial.add_microdesc(unwanted);
+        assert_eq!(partial.netdir.version(), last);
+        // Other directories get versions of their own.
+        assert_ne!(testnet::partial_netdir(&relays).netdir.version(), last);
+    }
+
+    #[test]
+    fn lookup_by_id() {
+        let relays = testnet::default_relays();
+        let netdir = testnet::netdir(&relays);
+        assert!(netdir.rsa_idx.is_some() && netdir.ed_idx.is_some());
+        // The same directory, but always looking relays up with a
+        // linear scan.
+        let mut linear = netdir.clone();
+        linear.rsa_idx = None;
+        linear.ed_idx = None;
+
+        for dir in &[&netdir, &linear] {
+            for idx in 0..relays.len() {
+                let (rsa, ed) = (testnet::rsa_id(idx), testnet::ed_id(idx));
+                assert!(dir.has_relay_with_rsa_id(&rsa));
+                assert!(dir.has_relay_with_ed25519_id(&ed));
+                assert_eq!(dir.relay_by_rsa_id(&rsa).unwrap().id(), &ed);
+                assert_eq!(dir.relay_by_id(&ed).unwrap().rsa_id(), &rsa);
+            }
+            let (rsa, ed) = (testnet::rsa_id(99), testnet::ed_id(99));
+            assert!(!dir.has_relay_with_rsa_id(&rsa));
+            assert!(!dir.has_relay_with_ed25519_id(&ed));
+            assert!(dir.relay_by_rsa_id(&rsa).is_none());
+            assert!(dir.relay_by_id(&ed).is_none());
+        }
+    }
+
+    #[test]
+    fn lookup_by_id_after_add() {
+        let relays = testnet::default_relays();
+        let mut mds = testnet::microdescs(&relays);
+        // Leave out relay 2, which has the least middle bandwidth.
+        let md2 = mds.remove(2);
+        let mut partial = testnet::partial_netdir(&relays);
+        for md in mds {
+            partial.add_microdesc(md);
+        }
+        let mut netdir = partial.unwrap_if_sufficient().unwrap();
+        let (rsa, ed) = (testnet::rsa_id(2), testnet::ed_id(2));
+        assert!(!netdir.has_relay_with_rsa_id(&rsa));
+        assert!(!netdir.has_relay_with_ed25519_id(&ed));
+        assert!(netdir.relay_by_id(&ed).is_none());
+
+        assert!(netdir.add_microdesc(md2));
+        assert!(netdir.has_relay_with_rsa_id(&rsa));
+        assert!(netdir.has_relay_with_ed25519_id(&ed));
+        assert_eq!(netdir.relay_by_id(&ed).unwrap().rsa_id(), &rsa);
+    }
+
+    #[test]
+    fn weighted_relay_iter() {
+        let netdir = testnet::netdir(&testnet::default_relays());
+        let ordered: Vec<_> = netdir
+            .weighted_relay_iter(WeightRole::Middle)
+            .map(|(r, w)| (*r.rsa_id(), w))
+            .collect();
+        assert_eq!(ordered.len(), 12);
+        for pair in ordered.windows(2) {
+            assert!(pair[0].1 >= pair[1].1);
+        }
+        // Only the middle-only relays have middle weight, and the rest
+        // keep their consensus order.
+        let expected: Vec<_> = [11, 8, 5, 2, 0, 1, 3, 4, 6, 7, 9, 10]
+            .iter()
+            .map(|idx| testnet::rsa_id(*idx))
+            .collect();
+        let got: Vec<_> = ordered.iter().map(|(id, _)| *id).collect();
+        assert_eq!(got, expected);
+        assert!(ordered[3].1 > 0);
+        assert_eq!(ordered[4].1, 0);
+    }
+
+    #[test]
+    fn pick_relay_by_role() {
+        let mut relays = testnet::default_relays();
+        // Relay 4 only allows exiting to port 443.
+        relays[4].exit_policy = "accept 443".into();
+        let netdir = testnet::netdir(&relays);
+        let mut rng = rand::thread_rng();
+        let ids = |idxs: &[usize]| -> HashSet<RsaIdentity> {
+            idxs.iter().map(|idx| testnet::rsa_id(*idx)).collect()
+        };
+        let mut pick_all = |role: RelayRole| -> HashSet<RsaIdentity> {
+            (0..500)
+                .filter_map(|_| netdir.pick_relay_by_role(&mut rng, role))
+                .map(|r| *r.rsa_id())
+                .collect()
+        };
+
+        assert_eq!(pick_all(RelayRole::Guard), ids(&[0, 3, 6, 9]));
+        assert_eq!(pick_all(RelayRole::Exit { port: 80 }), ids(&[1, 7, 10]));
+        assert_eq!(pick_all(RelayRole::Exit { port: 443 }), ids(&[1, 4, 7, 10]));
+        assert_eq!(pick_all(RelayRole::Exit { port: 22 }), ids(&[]));
+        assert_eq!(pick_all(RelayRole::Middle), ids(&[2, 5, 8, 11]));
+    }
+
+    #[test]
+    fn relay_scores() {
+        let netdir = testnet::netdir(&testnet::default_relays());
+        let ids = |scores: Vec<(Relay<'_>, u64)>| -> Vec<RsaIdentity> {
+            scores.iter().map(|(r, _)| *r.rsa_id()).collect()
+        };
+
+        let by_bw = netdir.relay_scores(|_, bw| bw.into());
+        assert_eq!(by_bw[0].1, 12000);
+        let expected: Vec<_> = (0..12).rev().map(testnet::rsa_id).collect();
+        assert_eq!(ids(by_bw), expected);
+
+        // Ties stay in consensus order.
+        let guards_first = netdir.relay_scores(|r, _| r.rs.is_flagged_guard().into());
+        let expected: Vec<_> = [0, 3, 6, 9, 1, 2, 4, 5, 7, 8, 10, 11]
+            .iter()
+            .map(|idx| testnet::rsa_id(*idx))
+            .collect();
+        assert_eq!(ids(guards_first), expected);
+    }
+
+    #[test]
+    fn shared_partial_netdir_is_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<PartialNetDir>();
+        assert_send_sync::<SharedPartialNetDir>();
+    }
+
+    #[test]
+    fn exit_policy_summary() {
+        let policy: PortPolicy = "accept 1-3,80,443,65000-65535".parse().unwrap();
+        let s = ExitPolicySummary::from_accepted(policy.allowed_ranges().to_vec());
+        let fmt = |v: &[PortRange]| v.iter().map(|r| r.to_string()).collect::<Vec<_>>();
+        assert_eq!(fmt(s.accepted()), vec!["1-3", "80", "443", "65000-65535"]);
+        assert_eq!(fmt(s.rejected()), vec!["4-79", "81-442", "444-64999"]);
+        assert_eq!(
+            s.allowed_ports().take(5).collect::<Vec<_>>(),
+            vec![1, 2, 3, 80, 443]
+        );
+        assert_eq!(s.allowed_ports().count(), 3 + 1 + 1 + 536);
+
+        let none = ExitPolicySummary::from_accepted(Vec::new());
+        assert_eq!(fmt(none.rejected()), vec!["1-65535"]);
+        assert_eq!(none.allowed_ports().next(), None);
     }
 }
@@ -129,6 +129,13 @@ caret_enum! {
         /// Whether clients should include Ed25519 identities for
         /// relays when generating EXTEND2 cells.
         ExtendByEd25519Id ("ExtendByEd25519ID"),
+        /// The lowest consensus bandwidth weight that a relay needs
+        /// before we'll consider using it as a guard.
+        ///
+        /// The authorities don't currently vote on this: they already
+        /// take bandwidth into account when they assign the Guard flag.
+        /// With the default of zero, only the flag matters.
+        GuardMinBandwidth ("guard-min-bandwidth"),
         /// A percentage threshold that determines whether clients
         /// believe they have enough directory information to build
         /// circuits.
@@ -175,6 +182,7 @@ impl Param {
             BwWeightScale => P::new(10_000, 1..),
             CircWindow => P::new(1_000, 100..=1000),
             ExtendByEd25519Id => P::new(0, BOOLEAN),
+            GuardMinBandwidth => P::new(0, 0..),
             CircuitPriorityHalflifeMsec => P::new(30_000, 1..),
             MinPathsForCircsPct => P::new(60, 25..=95),
             SendmeAcceptMinVersion => P::new(0, 0..=255),
@@ -253,6 +261,7 @@ mod test {
 
         assert_eq!(p.get(Param::BwWeightScale), 10_000);
         assert_eq!(p.get(Param::CircWindow), 1_000);
+        assert_eq!(p.get(Param::GuardMinBandwidth), 0);
     }
 
     #[test]
@@ -1,4 +1,4 @@
-//! Declare helper function for chosing from a weighted iterator
+//! Declare helper functions for chosing from a weighted iterator
 
 /// Choose a nonuniform random member of an iterator.
 ///
@@ -73,6 +73,79 @@ where
     result
 }
 
+/// A precomputed table for picking indices with probability
+/// proportional to a fixed list of weights, using Walker's alias
+/// method.
+///
+/// Building the table takes O(n) time; each pick after that takes
+/// O(1) time, no matter how many weights there are.
+#[derive(Debug, Clone)]
+pub(crate) struct AliasTable {
+    /// The index into the original weight list for each column.
+    ///
+    /// Only indices with nonzero weight appear here, so that we can
+    /// never pick an index with zero weight.
+    index: Vec<usize>,
+    /// For each column, the probability of picking that column's own
+    /// index rather than its alias.
+    prob: Vec<f64>,
+    /// For each column, the column to use instead if we don't pick
+    /// its own index.
+    alias: Vec<usize>,
+}
+
+impl AliasTable {
+    /// Build a new AliasTable for `weights`.
+    ///
+    /// Return None if there are no weights, or all of them are zero.
+    pub(crate) fn new(weights: &[u64]) -> Option<Self> {
+        let index: Vec<usize> = (0..weights.len()).filter(|&i| weights[i] > 0).collect();
+        if index.is_empty() {
+            return None;
+        }
+        let n = index.len();
+        // We sum in floating point, so that we can't overflow.
+        let total: f64 = index.iter().map(|&i| weights[i] as f64).sum();
+        // Scale each weight so that the average weight is 1.0.
+        let mut scaled: Vec<f64> = index
+            .iter()
+            .map(|&i| weights[i] as f64 * n as f64 / total)
+            .collect();
+
+        let mut prob = vec![1.0; n];
+        let mut alias: Vec<usize> = (0..n).collect();
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..n).partition(|&c| scaled[c] < 1.0);
+        while let (Some(s), Some(l)) = (small.pop(), large.pop()) {
+            // Column `s` gets all of its own weight, and fills the
+            // rest of its space with weight taken from `l`.
+            prob[s] = scaled[s];
+            alias[s] = l;
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                small.push(l);
+            } else {
+                large.push(l);
+            }
+        }
+        // Anything left over has (up to rounding error) a scaled weight
+        // of 1.0, so it keeps the default probability of 1.0.
+
+        Some(AliasTable { index, prob, alias })
+    }
+
+    /// Pick an index into the original weight list, with probability
+    /// proportional to its weight.
+    pub(crate) fn pick<R: rand::Rng>(&self, rng: &mut R) -> usize {
+        let col = rng.gen_range(0..self.index.len());
+        if rng.gen::<f64>() < self.prob[col] {
+            self.index[col]
+        } else {
+            self.index[self.alias[col]]
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -186,6 +259,39 @@ mod test {
         check_close(cnt[3], n_iters / 4);
     }
 
+    #[test]
+    fn alias_probabilistic() {
+        let arry: &[u64] = &[100, 0, 1000, 1];
+        let table = AliasTable::new(arry).unwrap();
+        let mut rng = get_rng();
+        let n_iters = get_iters() as isize;
+        let mut cnt = [0_isize; 4];
+        for _ in 0..n_iters {
+            cnt[table.pick(&mut rng)] += 1;
+        }
+        assert_eq!(cnt[1], 0);
+        check_close(cnt[0], (n_iters * 100) / 1101);
+        check_close(cnt[2], (n_iters * 1000) / 1101);
+        check_close(cnt[3], (n_iters) / 1101);
+
+        // Huge weights shouldn't overflow anything.
+        let table = AliasTable::new(&[u64::MAX; 4]).unwrap();
+        let mut cnt = [0_isize; 4];
+        for _ in 0..n_iters {
+            cnt[table.pick(&mut rng)] += 1;
+        }
+        for c in cnt.iter() {
+            check_close(*c, n_iters / 4);
+        }
+
+        assert!(AliasTable::new(&[]).is_none());
+        assert!(AliasTable::new(&[0, 0, 0]).is_none());
+        let table = AliasTable::new(&[0, 7, 0]).unwrap();
+        for _ in 0..100 {
+            assert_eq!(table.pick(&mut rng), 1);
+        }
+    }
+
     /// Try picking at random when no member can be chosen.
     #[test]
     fn zero_prob() {
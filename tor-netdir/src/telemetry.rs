@@ -0,0 +1,29 @@
+//! Hooks for observing which relays get picked.
+//!
+//! This module is only available if the crate was built with its
+//! `selection-telemetry` feature.  It lets researchers see which relays
+//! are chosen for which positions in a circuit, without changing how
+//! they are chosen.
+
+use crate::Relay;
+
+/// A position in a circuit for which a relay was selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CircuitPosition {
+    /// The first hop of a circuit.
+    Guard,
+    /// A hop in the middle of a circuit.
+    Middle,
+    /// The last hop of a circuit, when it exits to the internet.
+    Exit,
+    /// A directory cache that we connect to with a one-hop circuit.
+    DirCache,
+}
+
+/// An observer that is told about every relay selected with
+/// [`NetDir::pick_relay_with_telemetry`](crate::NetDir::pick_relay_with_telemetry).
+pub trait SelectionTelemetry {
+    /// Record that `relay` was selected for `position`.
+    fn relay_selected(&self, relay: &Relay<'_>, position: CircuitPosition);
+}
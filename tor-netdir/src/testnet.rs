@@ -0,0 +1,266 @@
+//! Build synthetic network directories for use in tests.
+//!
+//! The functions here turn a short description of each relay into a
+//! consensus and a matching set of microdescriptors, so that tests can
+//! check path selection and directory bookkeeping against a network
+//! whose shape they control.
+//!
+//! Nothing here is signed or timely: the documents are accepted with
+//! `dangerously_assume_wellsigned` and `dangerously_assume_timely`.
+//! Never use them outside of tests.
+//!
+//! This module is only available if the crate was built with its
+//! `testing` feature.
+
+use crate::{MdReceiver, NetDir, PartialNetDir};
+use tor_checkable::{ExternallySigned, Timebound};
+use tor_llcrypto::pk::ed25519::Ed25519Identity;
+use tor_llcrypto::pk::rsa::RsaIdentity;
+use tor_netdoc::doc::microdesc::Microdesc;
+use tor_netdoc::doc::netstatus::MdConsensus;
+
+use digest::Digest;
+use std::fmt::Write;
+use std::net::Ipv4Addr;
+
+/// The header of every consensus we generate.
+///
+/// (This is the header of tor-netdoc's `mdconsensus1.txt`.)
+const CONSENSUS_HEADER: &str = "\
+network-status-version 3 microdesc\n\
+vote-status consensus\n\
+consensus-method 28\n\
+valid-after 2020-08-07 12:42:40\n\
+fresh-until 2020-08-07 12:43:00\n\
+valid-until 2020-08-07 12:43:20\n\
+voting-delay 4 4\n\
+known-flags Authority Exit Fast Guard HSDir NoEdConsensus Running Stable V2Dir Valid\n\
+recommended-client-protocols Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 Link=4 Microdesc=1-2 Relay=2\n\
+recommended-relay-protocols Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 Link=4 Microdesc=1-2 Relay=2\n\
+required-client-protocols Cons=1-2 Desc=1-2 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 Link=4 Microdesc=1-2 Relay=2\n\
+required-relay-protocols Cons=1 Desc=1 DirCache=1 HSDir=1 HSIntro=3 HSRend=1 Link=3-4 Microdesc=1 Relay=1-2\n\
+dir-source test001a 5696AB38CB3852AFA476A5C07B2D4788963D5567 127.0.0.1 127.0.0.1 7001 5001\n\
+contact auth1@test.test\n\
+vote-digest 32902D6653D3CBD4F709C1E788E7188A4514B469\n\
+dir-source test000a 5A23BA701776C9C1AB1C06E734E92AB3D5350D64 127.0.0.1 127.0.0.1 7000 5000\n\
+contact auth0@test.test\n\
+vote-digest BD69154582ADD167985FBA5B6BEF39A48FCF57E4\n\
+dir-source test002a 7C47DCB4A90E2C2B7C7AD27BD641D038CF5D7EBE 127.0.0.1 127.0.0.1 7002 5002\n\
+contact auth2@test.test\n\
+vote-digest 279F105B47E08D18391BC83CA862AD7E949BF16B\n\
+";
+
+/// The footer of every consensus we generate.
+///
+/// The signature is never checked.
+const CONSENSUS_FOOTER: &str = "\
+directory-footer\n\
+bandwidth-weights Wbd=3333 Wbe=0 Wbg=0 Wbm=10000 Wdb=10000 Web=10000 Wed=3333 Wee=10000 Weg=3333 Wem=10000 Wgb=10000 Wgd=3333 Wgg=10000 Wgm=10000 Wmb=10000 Wmd=3333 Wme=0 Wmg=0 Wmm=10000\n\
+directory-signature sha256 5696AB38CB3852AFA476A5C07B2D4788963D5567 F6ED4AA64D83CAEDE34E19693A7FCF331AAE8A6A\n\
+-----BEGIN SIGNATURE-----\n\
+UUu8VroTU5iELNuh9sQAep3KIBmB8foN4Vil3pC6m+1G+iJFxCeMotXW5pQANLwu\n\
+WT5rX8wI3w5YT8MX2xADUN0PlG7YRBirBVeE6i/C42D5AN6ecqWLF78h1+CyktNb\n\
+g09SHub48vyjTJw+17HpVrhS+UzhF5s9C9yVpoRSr9vizxX2pV2o6e6XeIsQjOmd\n\
+6QjjA/8YHrXTshYr6baeZGh8ApeqLsuG+4YZMABkxVlRWo5Fmk4vJbd6MRRsLJwK\n\
+ePPyjBmSpRINJsBSTsgbz5YaIqtXVp+78F1VnlY1/4v9K+EUV90+y7HHsJKZnhIX\n\
+YooJRkMOJVWj37PDRVJ+1g==\n\
+-----END SIGNATURE-----\n\
+";
+
+/// The onion keys that every generated microdescriptor lists.
+const ONION_KEYS: &str = "\
+onion-key
+-----BEGIN RSA PUBLIC KEY-----
+MIGJAoGBALiyRXkiUmFQAmd1+Sy2ApNiPE4F54H7dKUwRqvCs+PqpqExaM2ybqMd
+Zzjkgsp41jjOxk/m5RGyo+wLTo3C1TanWsf3CFfoL836KYiv9IjguIuH8FH/EpX1
+ss5ChhkkKNhPtvOMFfVe0iuN0abUdGSmixKUVNW0/LiNzXa9Fa/jAgMBAAE=
+-----END RSA PUBLIC KEY-----
+ntor-onion-key gkQqYIYcecF1KwS2wVV/oVQAuf6oeTWzswXFkEVs/nE=
+";
+
+/// The protocol versions that every generated relay lists.
+const PROTOCOLS: &str = "Cons=1-2 Desc=1-2 DirCache=1-2 HSDir=1-2 HSIntro=3-4 HSRend=1-2 \
+Link=1-5 LinkAuth=1,3 Microdesc=1-2 Relay=1-2";
+
+/// A description of one relay in a test network.
+///
+/// Relays are identified by their position in the network: see
+/// [`rsa_id`] and [`ed_id`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub struct TestRelay {
+    /// The flags to list for this relay, as they appear on an "s" line.
+    ///
+    /// They must be in sorted order.
+    pub flags: String,
+    /// The bandwidth to list for this relay.
+    pub bandwidth: u32,
+    /// The IPv4 address of this relay's ORPort.
+    pub addr: Ipv4Addr,
+    /// The IPv4 exit policy summary for this relay, as it appears on a
+    /// microdescriptor's "p" line.
+    pub exit_policy: String,
+    /// The positions of the relays that this relay lists in its family.
+    pub family: Vec<usize>,
+}
+
+impl TestRelay {
+    /// Return a description of the relay at position `idx`: a running,
+    /// valid directory cache that isn't a guard or an exit, with an
+    /// address in a /16 network of its own.
+    pub fn new(idx: usize) -> Self {
+        assert!(idx < 255, "too many relays for a test network");
+        TestRelay {
+            flags: "Fast Running Stable V2Dir Valid".into(),
+            bandwidth: 1000,
+            addr: Ipv4Addr::new(20, idx as u8, 0, 1),
+            exit_policy: "reject 1-65535".into(),
+            family: Vec::new(),
+        }
+    }
+
+    /// Add `flag` to the flags that this relay lists.
+    pub fn with_flag(mut self, flag: &str) -> Self {
+        // The consensus format requires flags in sorted order.
+        let mut flags: Vec<&str> = self.flags.split_whitespace().collect();
+        flags.push(flag);
+        flags.sort_unstable();
+        self.flags = flags.join(" ");
+        self
+    }
+}
+
+/// Return the RSA identity of the relay at position `idx` in a test
+/// network.
+pub fn rsa_id(idx: usize) -> RsaIdentity {
+    RsaIdentity::from_bytes(&[idx as u8 + 1; 20]).expect("wrong RSA identity length")
+}
+
+/// Return the Ed25519 identity of the relay at position `idx` in a test
+/// network.
+pub fn ed_id(idx: usize) -> Ed25519Identity {
+    Ed25519Identity::from_bytes(&[idx as u8 + 1; 32]).expect("wrong Ed25519 identity length")
+}
+
+/// Return a description of a small network that has enough guards,
+/// middles and exits to build paths.
+///
+/// Every third relay (starting with the first) is a guard, and every
+/// third relay (starting with the second) is an exit to ports 80 and
+/// 443.  Relays later in the list have more bandwidth.
+pub fn default_relays() -> Vec<TestRelay> {
+    (0..12)
+        .map(|idx| {
+            let mut relay = TestRelay::new(idx);
+            relay.bandwidth = 1000 * (idx as u32 + 1);
+            match idx % 3 {
+                0 => relay.with_flag("Guard"),
+                1 => {
+                    relay.exit_policy = "accept 80,443".into();
+                    relay.with_flag("Exit")
+                }
+                _ => relay,
+            }
+        })
+        .collect()
+}
+
+/// Return the text of the microdescriptor for the relay at position
+/// `idx` in `relays`.
+fn microdesc_text(relays: &[TestRelay], idx: usize) -> String {
+    let relay = &relays[idx];
+    let mut text = ONION_KEYS.to_string();
+    if !relay.family.is_empty() {
+        text.push_str("family");
+        for &other in relay.family.iter() {
+            write!(text, " ${}", hex::encode_upper(rsa_id(other).as_bytes())).unwrap();
+        }
+        text.push('\n');
+    }
+    writeln!(text, "p {}", relay.exit_policy).unwrap();
+    writeln!(
+        text,
+        "id ed25519 {}",
+        base64::encode_config(ed_id(idx).as_bytes(), base64::STANDARD_NO_PAD)
+    )
+    .unwrap();
+    text
+}
+
+/// Return the microdescriptors for every relay in `relays`.
+pub fn microdescs(relays: &[TestRelay]) -> Vec<Microdesc> {
+    (0..relays.len())
+        .map(|idx| {
+            Microdesc::parse(&microdesc_text(relays, idx)).expect("generated a bad microdesc")
+        })
+        .collect()
+}
+
+/// Return the text of a consensus that lists every relay in `relays`.
+fn consensus_text(relays: &[TestRelay]) -> String {
+    let mut text = CONSENSUS_HEADER.to_string();
+    for (idx, relay) in relays.iter().enumerate() {
+        let digest = tor_llcrypto::d::Sha256::digest(microdesc_text(relays, idx).as_bytes());
+        let b64 = |bytes: &[u8]| base64::encode_config(bytes, base64::STANDARD_NO_PAD);
+        writeln!(
+            text,
+            "r relay{} {} 2020-08-07 12:40:41 {} 9001 0",
+            idx,
+            b64(rsa_id(idx).as_bytes()),
+            relay.addr
+        )
+        .unwrap();
+        writeln!(text, "m {}", b64(&digest)).unwrap();
+        writeln!(text, "s {}", relay.flags).unwrap();
+        writeln!(text, "v Tor 0.4.5.7").unwrap();
+        writeln!(text, "pr {}", PROTOCOLS).unwrap();
+        writeln!(text, "w Bandwidth={}", relay.bandwidth).unwrap();
+    }
+    text.push_str(CONSENSUS_FOOTER);
+    text
+}
+
+/// Return a consensus that lists every relay in `relays`.
+pub fn consensus(relays: &[TestRelay]) -> MdConsensus {
+    let text = consensus_text(relays);
+    let (_, _, unchecked) = MdConsensus::parse(&text).expect("generated a bad consensus");
+    unchecked
+        .dangerously_assume_timely()
+        .dangerously_assume_wellsigned()
+}
+
+/// Return a directory for `relays` that has no microdescriptors yet.
+pub fn partial_netdir(relays: &[TestRelay]) -> PartialNetDir {
+    PartialNetDir::new(consensus(relays), None)
+}
+
+/// Return a complete directory for `relays`.
+///
+/// # Panics
+///
+/// Panics if `relays` doesn't describe a network with enough paths.
+pub fn netdir(relays: &[TestRelay]) -> NetDir {
+    let mut partial = partial_netdir(relays);
+    for md in microdescs(relays) {
+        partial.add_microdesc(md);
+    }
+    partial
+        .unwrap_if_sufficient()
+        .unwrap_or_else(|_| panic!("test network doesn't have enough paths"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn every_relay_usable() {
+        let relays = default_relays();
+        let netdir = netdir(&relays);
+        assert_eq!(netdir.relays().count(), relays.len());
+        for idx in 0..relays.len() {
+            let relay = netdir.relay_by_id(&ed_id(idx)).unwrap();
+            assert_eq!(relay.rsa_id(), &rsa_id(idx));
+        }
+    }
+}
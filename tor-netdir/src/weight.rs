@@ -45,11 +45,15 @@ where
     }
 }
 
-/// Internal: how should we find the base bandwidth of each relay?  This
+/// How should we find the base bandwidth of each relay?  This
 /// value is global over a whole directory, and depends on the bandwidth
 /// weights in the consensus.
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum BandwidthFn {
+///
+/// Use [`NetDir::weight_fn`](crate::NetDir::weight_fn) to find the
+/// function that a given directory uses.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BandwidthFn {
     /// There are no weights at all in the consensus: weight every
     /// relay as 1.
     Uniform,
@@ -63,7 +67,7 @@ enum BandwidthFn {
 impl BandwidthFn {
     /// Apply this function to the measured or unmeasured bandwidth
     /// of a single router.
-    fn apply(&self, w: &RouterWeight) -> u32 {
+    pub fn apply(&self, w: &RouterWeight) -> u32 {
         use BandwidthFn::*;
         use RouterWeight::*;
         match (self, w) {
@@ -98,6 +102,39 @@ pub enum WeightRole {
     Unweighted,
 }
 
+/// A position in a circuit that we want to pick a relay for.
+///
+/// Unlike [`WeightRole`], this also says which relays are suitable for
+/// the position at all; see [`NetDir::pick_relay_by_role`](crate::NetDir::pick_relay_by_role).
+#[derive(Clone, Debug, Copy)]
+#[non_exhaustive]
+pub enum RelayRole {
+    /// The first hop of a circuit: only relays with the Guard flag.
+    Guard,
+    /// A middle hop of a circuit: any relay.
+    Middle,
+    /// The last hop of a circuit, which must allow exiting to `port`
+    /// over IPv4.
+    Exit {
+        /// The port that the exit must support.
+        port: u16,
+    },
+    /// A rendezvous point for an onion service circuit: any relay,
+    /// weighted as a middle hop.
+    Rendezvous,
+}
+
+impl RelayRole {
+    /// Return the WeightRole whose bandwidth weights apply to this role.
+    pub(crate) fn weight_role(&self) -> WeightRole {
+        match self {
+            RelayRole::Guard => WeightRole::Guard,
+            RelayRole::Middle | RelayRole::Rendezvous => WeightRole::Middle,
+            RelayRole::Exit { .. } => WeightRole::Exit,
+        }
+    }
+}
+
 /// Description for how to weight a single kind of relay for each WeightRole.
 #[derive(Clone, Debug, Copy)]
 struct RelayWeight {
@@ -209,6 +246,10 @@ pub(crate) struct WeightSet {
 }
 
 impl WeightSet {
+    /// Return the function we use to find the base bandwidth of each relay.
+    pub(crate) fn bandwidth_fn(&self) -> BandwidthFn {
+        self.bandwidth_fn
+    }
     /// Find the actual 64-bit weight to use for a given routerstatus when
     /// considering it for a given role.
     pub(crate) fn weight_rs_for_role(&self, rs: &MdConsensusRouterStatus, role: WeightRole) -> u64 {
@@ -460,6 +501,58 @@ mod test {
     const TESTVEC_PARAMS: &str =
         "Wbd=0 Wbe=0 Wbg=4096 Wbm=10000 Wdb=10000 Web=10000 Wed=10000 Wee=10000 Weg=10000 Wem=10000 Wgb=10000 Wgd=0 Wgg=5904 Wgm=5904 Wmb=10000 Wmd=0 Wme=0 Wmg=4096 Wmm=10000";
 
+    #[test]
+    fn t_relay_role_weights() {
+        use rand::SeedableRng;
+        let params = TESTVEC_PARAMS.parse().unwrap();
+        let ws = WeightSet::from_parts(BandwidthFn::MeasuredOnly, 1_000_000_000, 10000, &params);
+
+        // A guard and a plain relay with the same bandwidth: as middle
+        // hops (or rendezvous points), they're weighted by Wmg and Wmm.
+        let kinds = [FLG_GUARD, 0];
+        for role in &[RelayRole::Middle, RelayRole::Rendezvous] {
+            let weight = |k: &&u8| {
+                ws.weight_bw_for_role(
+                    WeightKind(**k),
+                    &RouterWeight::Measured(1000),
+                    role.weight_role(),
+                )
+            };
+            assert_eq!(weight(&&kinds[0]), 4096 * 1000);
+            assert_eq!(weight(&&kinds[1]), 10000 * 1000);
+
+            let mut rng = rand::rngs::StdRng::from_seed(*b"George Alfred Julius Totalisator");
+            let n_iters = 5000;
+            let n_guard = (0..n_iters)
+                .filter(|_| {
+                    let k = crate::pick::pick_weighted(&mut rng, kinds.iter(), weight).unwrap();
+                    *k == FLG_GUARD
+                })
+                .count();
+            let expected = 4096.0 / 14096.0;
+            let got = n_guard as f64 / n_iters as f64;
+            assert!((got - expected).abs() < 0.03);
+        }
+
+        // As guards, guards are weighted by Wgg; as exits, exits by Wee.
+        assert_eq!(
+            ws.weight_bw_for_role(
+                WeightKind(FLG_GUARD),
+                &RouterWeight::Measured(1000),
+                RelayRole::Guard.weight_role()
+            ),
+            5904 * 1000
+        );
+        assert_eq!(
+            ws.weight_bw_for_role(
+                WeightKind(FLG_EXIT),
+                &RouterWeight::Measured(1000),
+                RelayRole::Exit { port: 443 }.weight_role()
+            ),
+            10000 * 1000
+        );
+    }
+
     #[test]
     fn t_weightset_basic() {
         let total_bandwidth = 1_000_000_000;
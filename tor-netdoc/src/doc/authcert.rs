@@ -450,6 +450,16 @@ mod test {
             ),
         );
         check("bad-version", Error::BadDocumentVersion(4));
+        // Versions 1 and 2 predate the current format; we only accept 3.
+        for v in &[1, 2] {
+            let contents = bad_data("bad-version").replacen(
+                "dir-key-certificate-version 4",
+                &format!("dir-key-certificate-version {}", v),
+                1,
+            );
+            let cert = AuthCert::parse(&contents);
+            assert_eq!(cert.err().unwrap(), Error::BadDocumentVersion(*v));
+        }
         check(
             "wrong-end",
             Error::WrongEndingToken("dir-key-crosscert".into(), Pos::from_line(37, 1)),
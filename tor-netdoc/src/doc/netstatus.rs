@@ -374,6 +374,15 @@ bitflags! {
         /// Set if this relay supports a currently recognized version of the
         /// directory protocol.
         const V2DIR = (1<<11);
+        /// Set if this relay enforces the predefined "reduced" exit
+        /// policy, rather than one of its own.
+        const REDUCED_EXIT_POLICY = (1<<12);
+        /// Set if this relay has reported that it is overloaded in
+        /// general (for example, out of memory or CPU).
+        const OVERLOAD_GENERAL = (1<<13);
+        /// Set if this relay has reported that it is hitting its
+        /// configured bandwidth rate limit.
+        const OVERLOAD_RATE_LIMIT = (1<<14);
     }
 }
 
@@ -470,6 +479,8 @@ pub struct Consensus<RS> {
     pub routers: Vec<RS>,
     /// Footer for the consensus object.
     footer: Footer,
+    /// The SHA256 digest of the signed part of this consensus.
+    signed_sha256: [u8; 32],
 }
 
 /// A consensus document that lists relays along with their
@@ -517,6 +528,15 @@ impl<RS> Consensus<RS> {
     pub fn params(&self) -> &NetParams<i32> {
         &self.header.hdr.params
     }
+
+    /// Return the SHA256 digest of the signed part of this consensus:
+    /// everything up to and including the first `directory-signature `.
+    ///
+    /// This identifies this exact consensus document, whatever its
+    /// flavor.
+    pub fn signed_sha256(&self) -> &[u8; 32] {
+        &self.signed_sha256
+    }
 }
 
 decl_keyword! {
@@ -965,6 +985,9 @@ impl std::str::FromStr for RouterFlags {
             "Running" => RouterFlags::RUNNING,
             "Valid" => RouterFlags::VALID,
             "V2Dir" => RouterFlags::V2DIR,
+            "ReducedExitPolicy" => RouterFlags::REDUCED_EXIT_POLICY,
+            "OverloadGeneral" => RouterFlags::OVERLOAD_GENERAL,
+            "OverloadRateLimit" => RouterFlags::OVERLOAD_RATE_LIMIT,
             _ => RouterFlags::empty(),
         })
     }
@@ -1256,13 +1279,6 @@ impl<RS: ParseRouterStatus + RouterStatus> Consensus<RS> {
 
         let footer = Self::take_footer(r)?;
 
-        let consensus = Consensus {
-            header,
-            voters,
-            routers,
-            footer,
-        };
-
         // Find the signatures.
         let mut first_sig: Option<Item<'_, NetstatusKwd>> = None;
         let mut signatures = Vec::new();
@@ -1304,10 +1320,20 @@ impl<RS: ParseRouterStatus + RouterStatus> Consensus<RS> {
             signatures,
         };
 
+        let consensus = Consensus {
+            header,
+            voters,
+            routers,
+            footer,
+            signed_sha256: sha256
+                .unwrap_or_else(|| ll::d::Sha256::digest(signed_str.as_bytes()).into()),
+        };
+
         let unval = UnvalidatedConsensus {
             consensus,
             siggroup,
             n_authorities: None,
+            threshold: None,
         };
         let lifetime = unval.consensus.header.hdr.lifetime.clone();
         let delay = unval.consensus.header.hdr.voting_delay.unwrap_or((0, 0));
@@ -1336,6 +1362,9 @@ pub struct UnvalidatedConsensus<RS> {
     /// this information in order to validate the signatures, since it
     /// determines how many signatures we need to find valid in `siggroup`.
     n_authorities: Option<u16>,
+    /// If set, the number of valid authority signatures that we need,
+    /// instead of more than half of `n_authorities`.
+    threshold: Option<u16>,
 }
 
 impl<RS> UnvalidatedConsensus<RS> {
@@ -1349,6 +1378,29 @@ impl<RS> UnvalidatedConsensus<RS> {
         }
     }
 
+    /// Tell the unvalidated consensus how many valid authority
+    /// signatures it needs.
+    ///
+    /// By default, a consensus needs signatures from more than half of
+    /// the authorities passed to [`Self::set_n_authorities`].  Private
+    /// networks with few authorities may want a different threshold.
+    pub fn set_threshold(self, n_needed: u16) -> Self {
+        UnvalidatedConsensus {
+            threshold: Some(n_needed),
+            ..self
+        }
+    }
+
+    /// Helper: return the number of valid signatures we need, if we
+    /// know it.
+    fn n_needed(&self) -> Option<usize> {
+        match (self.threshold, self.n_authorities) {
+            (Some(t), _) => Some(t.into()),
+            (None, Some(n)) => Some((n / 2) as usize + 1),
+            (None, None) => None,
+        }
+    }
+
     /// Return an iterator of all the certificate IDs that we might use
     /// to validate this consensus.
     pub fn signing_cert_ids(&self) -> impl Iterator<Item = AuthCertKeyIds> {
@@ -1369,9 +1421,22 @@ impl<RS> UnvalidatedConsensus<RS> {
     /// well-signed.
     ///
     /// (This is the case if the consensus claims to be signed by more than
-    /// half of the authorities in the list.)
+    /// half of the authorities in the list, or by as many as we require
+    /// after [`Self::set_threshold`].)
     pub fn authorities_are_correct(&self, authorities: &[&RsaIdentity]) -> bool {
-        self.siggroup.could_validate(authorities)
+        let n_needed = match self.threshold {
+            Some(t) => t.into(),
+            None => authorities.len() / 2 + 1,
+        };
+        self.siggroup.could_validate(authorities, n_needed)
+    }
+
+    /// Return the number of distinct authorities in `authorities` that
+    /// purport to have signed this consensus.
+    ///
+    /// The signatures are not checked.
+    pub fn n_signers_among(&self, authorities: &[&RsaIdentity]) -> usize {
+        self.siggroup.n_signed_by(authorities)
     }
 }
 
@@ -1382,16 +1447,17 @@ impl<RS> ExternallySigned<Consensus<RS>> for UnvalidatedConsensus<RS> {
 
     fn key_is_correct(&self, k: &Self::Key) -> result::Result<(), Self::KeyHint> {
         let (n_ok, missing) = self.siggroup.list_missing(k);
-        match self.n_authorities {
-            Some(n) if n_ok > (n / 2) as usize => Ok(()),
+        match self.n_needed() {
+            Some(n) if n_ok >= n => Ok(()),
             _ => Err(missing.iter().map(|cert| cert.key_ids).collect()),
         }
     }
     fn is_well_signed(&self, k: &Self::Key) -> result::Result<(), Self::Error> {
-        if self.n_authorities.is_none() {
-            return Err(Error::Internal(Pos::None));
-        }
-        if self.siggroup.validate(self.n_authorities.unwrap(), k) {
+        let n_needed = match self.n_needed() {
+            Some(n) => n,
+            None => return Err(Error::Internal(Pos::None)),
+        };
+        if self.siggroup.validate(n_needed, k) {
             Ok(())
         } else {
             Err(Error::BadSignature(Pos::None))
@@ -1428,9 +1494,15 @@ impl SignatureGroup {
     }
 
     /// Given a list of authority identity key fingerprints, return true if
-    /// this signature group is _potentially_ well-signed according to those
-    /// authorities.
-    fn could_validate(&self, authorities: &[&RsaIdentity]) -> bool {
+    /// this signature group is _potentially_ well-signed by at least
+    /// `n_needed` of those authorities.
+    fn could_validate(&self, authorities: &[&RsaIdentity], n_needed: usize) -> bool {
+        self.n_signed_by(authorities) >= n_needed
+    }
+
+    /// Return the number of distinct authorities in `authorities` that
+    /// have purportedly signed this signature group.
+    fn n_signed_by(&self, authorities: &[&RsaIdentity]) -> usize {
         let mut signed_by: HashSet<RsaIdentity> = HashSet::new();
         for sig in self.signatures.iter() {
             let id_fp = &sig.key_ids.id_fingerprint;
@@ -1443,16 +1515,15 @@ impl SignatureGroup {
             }
         }
 
-        signed_by.len() > (authorities.len() / 2)
+        signed_by.len()
     }
 
     /// Return true if the signature group defines a valid signature.
     ///
-    /// A signature is valid if it signed by more than half of the
-    /// authorities.  This API requires that `n_authorities` is the number of
-    /// authorities we believe in, and that every cert in `certs` belongs
+    /// A signature is valid if it signed by at least `n_needed`
+    /// authorities.  This API requires that every cert in `certs` belongs
     /// to a real authority.
-    fn validate(&self, n_authorities: u16, certs: &[AuthCert]) -> bool {
+    fn validate(&self, n_needed: usize, certs: &[AuthCert]) -> bool {
         // A set of the authorities (by identity) who have have signed
         // this document.  We use a set here in case `certs` has more
         // than one certificate for a single authority.
@@ -1485,7 +1556,7 @@ impl SignatureGroup {
             }
         }
 
-        ok.len() > (n_authorities / 2) as usize
+        ok.len() >= n_needed
     }
 }
 
@@ -1511,6 +1582,50 @@ mod test {
         fs::read_to_string(path).unwrap()
     }
 
+    #[test]
+    fn count_signers() -> Result<()> {
+        use tor_checkable::{SelfSigned, Timebound};
+        let mut certs = Vec::new();
+        for cert in AuthCert::parse_multiple(CERTS) {
+            certs.push(cert?.check_signature()?.dangerously_assume_timely());
+        }
+        let auth_ids: Vec<_> = certs.iter().map(|c| &c.key_ids().id_fingerprint).collect();
+
+        let (_, _, consensus) = MdConsensus::parse(CONSENSUS)?;
+        let consensus = consensus.dangerously_assume_timely();
+        assert_eq!(consensus.n_signers_among(&auth_ids), 3);
+
+        let bad_auth_id = (*b"xxxxxxxxxxxxxxxxxxxx").into();
+        assert_eq!(consensus.n_signers_among(&[&bad_auth_id]), 0);
+        assert_eq!(consensus.n_signers_among(&[auth_ids[0], &bad_auth_id]), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn custom_threshold() -> Result<()> {
+        use tor_checkable::{SelfSigned, Timebound};
+        let mut certs = Vec::new();
+        for cert in AuthCert::parse_multiple(CERTS) {
+            certs.push(cert?.check_signature()?.dangerously_assume_timely());
+        }
+        let auth_ids: Vec<_> = certs.iter().map(|c| &c.key_ids().id_fingerprint).collect();
+        let (_, _, consensus) = MdConsensus::parse(CONSENSUS)?;
+        let consensus = consensus.dangerously_assume_timely().set_n_authorities(3);
+
+        // Three signatures aren't enough if we need four.
+        let strict = consensus.clone().set_threshold(4);
+        assert!(!strict.authorities_are_correct(&auth_ids));
+        assert!(strict.key_is_correct(&certs[..]).is_err());
+        assert!(strict.is_well_signed(&certs[..]).is_err());
+
+        // One is enough if we only need one.
+        let lax = consensus.set_threshold(1);
+        assert!(lax.authorities_are_correct(&auth_ids[0..1]));
+        assert!(lax.key_is_correct(&certs[0..1]).is_ok());
+        assert!(lax.is_well_signed(&certs[0..1]).is_ok());
+        Ok(())
+    }
+
     #[test]
     fn parse_and_validate_md() -> Result<()> {
         use std::net::SocketAddr;
@@ -1557,6 +1672,11 @@ mod test {
         assert!(consensus.key_is_correct(&certs).is_ok());
         let consensus = consensus.check_signature(&certs)?;
 
+        let sig_pos = CONSENSUS.find("directory-signature ").unwrap();
+        let signed = &CONSENSUS[..sig_pos + "directory-signature ".len()];
+        let expected: [u8; 32] = ll::d::Sha256::digest(signed.as_bytes()).into();
+        assert_eq!(consensus.signed_sha256(), &expected);
+
         assert_eq!(6, consensus.routers().len());
         let r0 = &consensus.routers()[0];
         assert_eq!(
@@ -1576,6 +1696,9 @@ mod test {
         let ip6 = "[::1]:5002".parse::<SocketAddr>().unwrap();
         assert!(r0.orport_addrs().any(|a| a == &ip4));
         assert!(r0.orport_addrs().any(|a| a == &ip6));
+        // These flags are implicit in a consensus.
+        assert!(r0.is_flagged_running());
+        assert!(r0.is_flagged_valid());
 
         Ok(())
     }
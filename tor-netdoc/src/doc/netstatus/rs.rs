@@ -102,6 +102,14 @@ macro_rules! implement_accessors {
             pub fn flags(&self) -> &RouterFlags {
                 &self.rs.flags
             }
+            /// Return the declared publication time of this relay's
+            /// router descriptor.
+            ///
+            /// See proposal 275 for caveats: this time is not always
+            /// meaningful.
+            pub fn published(&self) -> time::SystemTime {
+                self.rs.published
+            }
             /// Return the version of this routerstatus.
             pub fn version(&self) -> &Option<String> {
                 &self.rs.version
@@ -119,6 +127,21 @@ macro_rules! implement_accessors {
             pub fn is_flagged_v2dir(&self) -> bool {
                 self.rs.flags.contains(RouterFlags::V2DIR)
             }
+            /// Return true if this routerstatus is listed with the
+            /// ReducedExitPolicy flag.
+            pub fn is_flagged_reduced_exit_policy(&self) -> bool {
+                self.rs.flags.contains(RouterFlags::REDUCED_EXIT_POLICY)
+            }
+            /// Return true if this routerstatus is listed with the
+            /// OverloadGeneral flag.
+            pub fn is_flagged_overload_general(&self) -> bool {
+                self.rs.flags.contains(RouterFlags::OVERLOAD_GENERAL)
+            }
+            /// Return true if this routerstatus is listed with the
+            /// OverloadRateLimit flag.
+            pub fn is_flagged_overload_rate_limit(&self) -> bool {
+                self.rs.flags.contains(RouterFlags::OVERLOAD_RATE_LIMIT)
+            }
             /// Return true if this routerstatus is listed with the Exit flag.
             pub fn is_flagged_exit(&self) -> bool {
                 self.rs.flags.contains(RouterFlags::EXIT)
@@ -127,6 +150,18 @@ macro_rules! implement_accessors {
             pub fn is_flagged_guard(&self) -> bool {
                 self.rs.flags.contains(RouterFlags::GUARD)
             }
+            /// Return true if this routerstatus is listed with the Running flag.
+            ///
+            /// (In a consensus, this is always true.)
+            pub fn is_flagged_running(&self) -> bool {
+                self.rs.flags.contains(RouterFlags::RUNNING)
+            }
+            /// Return true if this routerstatus is listed with the Valid flag.
+            ///
+            /// (In a consensus, this is always true.)
+            pub fn is_flagged_valid(&self) -> bool {
+                self.rs.flags.contains(RouterFlags::VALID)
+            }
         }
     };
 }
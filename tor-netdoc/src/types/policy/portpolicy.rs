@@ -106,6 +106,12 @@ impl PortPolicy {
             .binary_search_by(|range| range.compare_to_port(port))
             .is_ok()
     }
+    /// Return the port ranges that this policy allows.
+    ///
+    /// The ranges are sorted, disjoint, and never adjacent.
+    pub fn allowed_ranges(&self) -> &[PortRange] {
+        &self.allowed[..]
+    }
     /// Replace this PortPolicy with an interned copy, to save memory.
     pub fn intern(self) -> Arc<Self> {
         POLICY_CACHE.intern(self)
@@ -351,7 +351,9 @@ impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> UnverifiedChannel<T> {
         // We do this _last_, since "this is the wrong peer" is
         // usually a different situation than "this peer couldn't even
         // identify itself right."
-        if *peer.ed_identity() != ed25519_id {
+        // Targets without an Ed25519 identity are only checked by
+        // their RSA identity.
+        if peer.uses_ed25519_auth() && *peer.ed_identity() != ed25519_id {
             return Err(Error::ChanProto("Peer ed25519 id not as expected".into()));
         }
 
@@ -218,14 +218,35 @@ struct CircHop {
     auth_sendme_optional: bool,
     /// Window used to say how many cells we can send.
     sendwindow: sendme::CircSendWindow,
+    /// Initial send window to use for streams that exit from this hop.
+    stream_window: u16,
+}
+
+/// Settings for a circuit hop that depend on which relay it is.
+#[derive(Clone, Copy, Debug)]
+struct HopSettings {
+    /// True if this hop supports authenticated SENDMEs (FlowCtrl=1).
+    supports_flowctrl_1: bool,
+    /// Initial send window to use for streams that exit from this hop.
+    stream_window: u16,
+}
+
+impl Default for HopSettings {
+    fn default() -> Self {
+        HopSettings {
+            supports_flowctrl_1: false,
+            stream_window: StreamTarget::SEND_WINDOW_INIT,
+        }
+    }
 }
 
 impl CircHop {
     /// Construct a new (sender-side) view of a circuit hop.
-    fn new(auth_sendme_optional: bool, initial_window: u16) -> Self {
+    fn new(settings: HopSettings, initial_window: u16) -> Self {
         CircHop {
-            auth_sendme_optional,
+            auth_sendme_optional: settings.supports_flowctrl_1,
             sendwindow: sendme::CircSendWindow::new(initial_window),
+            stream_window: settings.stream_window,
         }
     }
 }
@@ -288,7 +309,7 @@ impl ClientCirc {
         handshake_id: u16,
         key: &H::KeyType,
         linkspecs: Vec<LinkSpec>,
-        supports_flowctrl_1: bool,
+        settings: HopSettings,
         params: &CircParameters,
     ) -> Result<()>
     where
@@ -386,13 +407,8 @@ impl ClientCirc {
 
         // If we get here, it succeeded.  Add a new hop to the circuit.
         let (layer_fwd, layer_back) = layer.split();
-        self.add_hop(
-            supports_flowctrl_1,
-            Box::new(layer_fwd),
-            Box::new(layer_back),
-            params,
-        )
-        .await
+        self.add_hop(settings, Box::new(layer_fwd), Box::new(layer_back), params)
+            .await
     }
 
     /// Add a hop to the end of this circuit.
@@ -403,7 +419,7 @@ impl ClientCirc {
     /// that it did.
     async fn add_hop<'a>(
         &'a self,
-        supports_flowctrl_1: bool,
+        settings: HopSettings,
         fwd: Box<dyn OutboundClientLayer + 'static + Send>,
         rev: Box<dyn InboundClientLayer + 'static + Send>,
         params: &'a CircParameters,
@@ -427,7 +443,7 @@ impl ClientCirc {
 
         {
             let mut c = self.c.lock().await;
-            let hop = CircHop::new(supports_flowctrl_1, params.initial_send_window);
+            let hop = CircHop::new(settings, params.initial_send_window);
             c.hops.push(hop);
             c.crypto_out.add_layer(fwd);
         }
@@ -462,13 +478,12 @@ impl ClientCirc {
         let supports_flowctrl_1 = target
             .protovers()
             .supports_known_subver(tor_protover::ProtoKind::FlowCtrl, 1);
-        self.extend_impl::<R, Tor1RelayCrypto, _, _, NtorClient>(
-            rng,
-            0x0002,
-            &key,
-            linkspecs,
+        let settings = HopSettings {
             supports_flowctrl_1,
-            params,
+            stream_window: target.stream_window_default(),
+        };
+        self.extend_impl::<R, Tor1RelayCrypto, _, _, NtorClient>(
+            rng, 0x0002, &key, linkspecs, settings, params,
         )
         .await
     }
@@ -488,14 +503,15 @@ impl ClientCirc {
         let (sender, receiver) = mpsc::channel(128);
 
         let (send_close, recv_close) = oneshot::channel::<CtrlMsg>();
-        let window = sendme::StreamSendWindow::new(StreamTarget::SEND_WINDOW_INIT);
 
         let (id_snd, id_rcv) = oneshot::channel();
         let hopnum;
+        let window;
         {
             let mut c = self.c.lock().await;
             let h = c.hops.len() - 1;
             hopnum = (h as u8).into();
+            window = sendme::StreamSendWindow::new(c.hops[h].stream_window);
 
             c.control
                 .send(Ok(CtrlMsg::AddStream(
@@ -830,7 +846,7 @@ impl PendingClientCirc {
         rng: &mut R,
         wrap: &W,
         key: &H::KeyType,
-        supports_flowctrl_1: bool,
+        settings: HopSettings,
         params: &CircParameters,
     ) -> Result<Arc<ClientCirc>>
     where
@@ -872,13 +888,8 @@ impl PendingClientCirc {
         debug!("{}: Handshake complete; circuit created.", unique_id);
 
         let (layer_fwd, layer_back) = layer.split();
-        circ.add_hop(
-            supports_flowctrl_1,
-            Box::new(layer_fwd),
-            Box::new(layer_back),
-            params,
-        )
-        .await?;
+        circ.add_hop(settings, Box::new(layer_fwd), Box::new(layer_back), params)
+            .await?;
         Ok(circ)
     }
 
@@ -903,7 +914,7 @@ impl PendingClientCirc {
             rng,
             &wrap,
             &(),
-            false,
+            HopSettings::default(),
             params,
         )
         .await
@@ -940,12 +951,12 @@ impl PendingClientCirc {
         let supports_flowctrl_1 = target
             .protovers()
             .supports_known_subver(tor_protover::ProtoKind::FlowCtrl, 1);
-        self.create_impl::<R, Tor1RelayCrypto, _, _, NtorClient, _>(
-            rng,
-            &wrap,
-            &key,
+        let settings = HopSettings {
             supports_flowctrl_1,
-            params,
+            stream_window: target.stream_window_default(),
+        };
+        self.create_impl::<R, Tor1RelayCrypto, _, _, NtorClient, _>(
+            rng, &wrap, &key, settings, params,
         )
         .await
     }
@@ -1004,7 +1015,8 @@ impl CreateHandshakeWrap for Create2Wrap {
 }
 
 impl StreamTarget {
-    /// Initial value for outbound flow-control window on streams.
+    /// Initial value for outbound flow-control window on streams, when
+    /// we don't have a CircTarget to ask.
     const SEND_WINDOW_INIT: u16 = 500;
 
     /// Deliver a relay message for the stream that owns this StreamTarget.
@@ -1274,7 +1286,10 @@ mod test {
             let params = CircParameters::default();
             let (hopf, reacf) = futures::join!(
                 circ.add_hop(
-                    true,
+                    HopSettings {
+                        supports_flowctrl_1: true,
+                        ..HopSettings::default()
+                    },
                     Box::new(DummyCrypto::new(idx == 2)),
                     Box::new(DummyCrypto::new(idx == next_msg_from.into())),
                     &params,